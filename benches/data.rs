@@ -0,0 +1,160 @@
+// generates the programs the benchmarks run. everything is seeded, so the
+// same workload comes out every time and numbers from two runs compare
+
+use std::fmt::Write;
+
+/// A program to load and the query that makes the engine do the work.
+pub struct Workload {
+    pub name: String,
+    pub program: String,
+    pub query: String,
+}
+
+const PATH: &str = "
+    path(X, Y) :- edge(X, Y).
+    path(X, Y) :- path(X, Z), edge(Z, Y).
+";
+
+// xorshift, plenty random enough for making up graphs
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+fn edges(edges: &[(String, String)]) -> String {
+    let mut out = String::new();
+    for (from, to) in edges {
+        writeln!(out, "edge({}, {}).", from, to).unwrap();
+    }
+    out
+}
+
+/// n0 -> n1 -> ... -> n{len}, every node reaches everything after it
+pub fn chain(len: usize) -> Workload {
+    let chain: Vec<_> = (0..len).map(|i| (format!("n{}", i), format!("n{}", i + 1))).collect();
+    Workload {
+        name: format!("closure/chain/{}", len),
+        program: edges(&chain) + PATH,
+        query: "path(X, Y)".to_string(),
+    }
+}
+
+/// a side x side grid with edges going right and down
+pub fn grid(side: usize) -> Workload {
+    let node = |x: usize, y: usize| format!("n{}_{}", x, y);
+    let mut grid = vec![];
+    for x in 0..side {
+        for y in 0..side {
+            if x + 1 < side {
+                grid.push((node(x, y), node(x + 1, y)));
+            }
+            if y + 1 < side {
+                grid.push((node(x, y), node(x, y + 1)));
+            }
+        }
+    }
+    Workload {
+        name: format!("closure/grid/{}x{}", side, side),
+        program: edges(&grid) + PATH,
+        query: "path(X, Y)".to_string(),
+    }
+}
+
+/// `count` edges between random pairs of `nodes` nodes, cycles and all
+pub fn random_graph(nodes: usize, count: usize) -> Workload {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let random: Vec<_> = (0..count)
+        .map(|_| (format!("n{}", rng.below(nodes)), format!("n{}", rng.below(nodes))))
+        .collect();
+    Workload {
+        name: format!("closure/random/{}-{}", nodes, count),
+        program: edges(&random) + PATH,
+        query: "path(X, Y)".to_string(),
+    }
+}
+
+/// a complete tree, everyone on a level is the same generation as everyone
+/// else on it
+pub fn same_generation(branching: usize, depth: usize) -> Workload {
+    let mut program = String::new();
+    let mut level = vec!["r".to_string()];
+    for _ in 0..depth {
+        let mut next = vec![];
+        for parent in &level {
+            for i in 0..branching {
+                let child = format!("{}{}", parent, i);
+                writeln!(program, "parent({}, {}).", parent, child).unwrap();
+                next.push(child);
+            }
+        }
+        level = next;
+    }
+    program.push_str(
+        "
+        person(X) :- parent(X, Y).
+        person(Y) :- parent(X, Y).
+        sg(X, X) :- person(X).
+        sg(X, Y) :- parent(P, X), sg(P, Q), parent(Q, Y).
+    ",
+    );
+    Workload {
+        name: format!("same-generation/{}^{}", branching, depth),
+        program,
+        query: "sg(X, Y)".to_string(),
+    }
+}
+
+/// Andersen's points-to analysis over a made up program with `vars`
+/// pointer variables and `statements` of each kind:
+///
+///   p = &o    addr_of(p, o)
+///   p = q     assign(p, q)
+///   p = *q    load(p, q)
+///   *p = q    store(p, q)
+pub fn points_to(vars: usize, statements: usize) -> Workload {
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    let mut program = String::new();
+    for kind in &["addr_of", "assign", "load", "store"] {
+        for _ in 0..statements {
+            let p = rng.below(vars);
+            let q = rng.below(vars);
+            match *kind {
+                "addr_of" => writeln!(program, "addr_of(v{}, o{}).", p, q).unwrap(),
+                _ => writeln!(program, "{}(v{}, v{}).", kind, p, q).unwrap(),
+            }
+        }
+    }
+    program.push_str(
+        "
+        pts(P, O) :- addr_of(P, O).
+        pts(P, O) :- assign(P, Q), pts(Q, O).
+        pts(P, O) :- load(P, Q), pts(Q, R), pts(R, O).
+        pts(R, O) :- store(P, Q), pts(P, R), pts(Q, O).
+    ",
+    );
+    Workload {
+        name: format!("points-to/{}-{}", vars, statements),
+        program,
+        query: "pts(X, Y)".to_string(),
+    }
+}
+
+/// a big base relation and a query for one key in it, which shouldn't cost
+/// much more than a lookup
+pub fn point_query(facts: usize) -> Workload {
+    let mut program = String::new();
+    for i in 0..facts {
+        writeln!(program, "value(k{}, {}).", i, i * 7 % 1000).unwrap();
+    }
+    Workload {
+        name: format!("point-query/{}", facts),
+        program,
+        query: format!("value(k{}, X)", facts / 2),
+    }
+}
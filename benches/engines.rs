@@ -0,0 +1,78 @@
+// cargo bench runs every workload in data.rs on every engine. only the query
+// is timed, loading the program happens before the clock starts:
+//
+//   cargo bench -- closure/grid       one workload
+//   cargo bench -- "top down"         one engine
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, SamplingMode};
+use datalog::internals::{program, query, DatalogEngine, RustEngine, SqliteEngine, Statement, TopDownEngine};
+use datalog::Error;
+
+mod data;
+
+// makes a fresh engine for every sample
+type NewEngine = fn() -> Box<dyn DatalogEngine>;
+
+fn engines() -> Vec<(&'static str, NewEngine)> {
+    vec![
+        ("bottom up", || Box::new(RustEngine::new())),
+        ("bottom up, 2 threads", || {
+            let mut e = RustEngine::new();
+            e.set_threads(2);
+            Box::new(e)
+        }),
+        ("top down", || Box::new(TopDownEngine::new())),
+        ("sqlite", || Box::new(SqliteEngine::new())),
+    ]
+}
+
+fn load(engine: &mut dyn DatalogEngine, statements: &[Statement]) -> Result<(), Error> {
+    for statement in statements {
+        match statement {
+            Statement::Fact(f) => {
+                engine.push_fact(f.clone())?;
+            }
+            Statement::Rule(r) => engine.push_rule(r.clone())?,
+            other => panic!("workloads are facts and rules, got {}", other),
+        }
+    }
+    Ok(())
+}
+
+fn workloads(c: &mut Criterion) {
+    // sized so top down, the slowest on most of these, still finishes in
+    // seconds. next to each, the engines that can't run it at all
+    let workloads: Vec<(data::Workload, &[&str])> = vec![
+        (data::chain(100), &[]),
+        (data::grid(10), &[]),
+        (data::random_graph(100, 200), &[]),
+        (data::same_generation(3, 4), &[]),
+        // load and store read pts twice in one rule, sqlite only recurses
+        // through one, and andersen's analysis doesn't go without them
+        (data::points_to(50, 50), &["sqlite"]),
+        (data::point_query(20_000), &[]),
+    ];
+    for (workload, skipped) in workloads {
+        let statements = program(&workload.program).unwrap();
+        let q = query(&workload.query).unwrap();
+        let mut group = c.benchmark_group(&workload.name);
+        group.sample_size(10).sampling_mode(SamplingMode::Flat);
+        for (name, new) in engines().into_iter().filter(|(name, _)| !skipped.contains(name)) {
+            group.bench_function(name, |b| {
+                b.iter_batched(
+                    || {
+                        let mut engine = new();
+                        load(engine.as_mut(), &statements).unwrap();
+                        engine
+                    },
+                    |engine| engine.query(q.clone()).unwrap(),
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, workloads);
+criterion_main!(benches);
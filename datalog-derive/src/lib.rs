@@ -0,0 +1,260 @@
+//! The macros of the `datalog` crate, use them from there:
+//!
+//! ```ignore
+//! use datalog::{datalog, Relation};
+//!
+//! #[derive(Relation)]
+//! struct Edge {
+//!     src: String,
+//!     dst: String,
+//! }
+//!
+//! let program = datalog! {
+//!     path(X, Y) :- edge(X, Y).
+//! };
+//! ```
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Delimiter, Span, TokenStream as Tokens, TokenTree};
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Lit, Meta, NestedMeta, Type};
+
+// the datalog crate's own parser and safety check, so datalog! accepts
+// exactly what the engine would. a proc macro crate can't depend on the
+// crate that depends on it, so they live in datalog-syntax for both
+use datalog_syntax::{ast, parser, safety};
+
+use crate::ast::{Statement, Variable};
+
+/*
+ * maps a struct with named fields onto a relation: the struct is the relation
+ * (snake_cased, or whatever #[datalog(name = "...")] says), each field a
+ * column in the order they're written. integer fields are number columns,
+ * everything else is a symbol written with Display and read back with FromStr
+ */
+
+#[proc_macro_derive(Relation, attributes(datalog))]
+pub fn derive_relation(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match relation(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn relation(input: &DeriveInput) -> syn::Result<Tokens> {
+    let ident = &input.ident;
+    let name = match relation_name(input)? {
+        Some(name) => name,
+        None => snake_case(&ident.to_string()),
+    };
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(named) => &named.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    ident,
+                    "Relation needs a struct with named fields, they name the columns",
+                ))
+            }
+        },
+        _ => return Err(syn::Error::new_spanned(ident, "Relation can only be derived for structs")),
+    };
+
+    let idents: Vec<_> = fields.iter().map(|f| f.ident.as_ref().unwrap()).collect();
+    let columns: Vec<String> = idents
+        .iter()
+        .map(|i| i.to_string().trim_start_matches("r#").to_string())
+        .collect();
+    let kinds: Vec<Tokens> = fields
+        .iter()
+        .map(|f| {
+            if is_integer(&f.ty) {
+                quote!(::datalog::ColumnType::Number)
+            } else {
+                quote!(::datalog::ColumnType::Symbol)
+            }
+        })
+        .collect();
+    let positions: Vec<usize> = (0..fields.len()).collect();
+    let arity = fields.len();
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::datalog::Relation for #ident #type_generics #where_clause {
+            const NAME: &'static str = #name;
+            const COLUMNS: &'static [(&'static str, ::datalog::ColumnType)] = &[
+                #((#columns, #kinds)),*
+            ];
+
+            fn values(&self) -> ::std::vec::Vec<::std::string::String> {
+                vec![#(::std::string::ToString::to_string(&self.#idents)),*]
+            }
+
+            fn from_values(values: &[&str]) -> ::std::result::Result<Self, ::datalog::Error> {
+                if values.len() != #arity {
+                    return ::std::result::Result::Err(::datalog::Error::Schema(format!(
+                        "{} has {} columns, got {}", #name, #arity, values.len()
+                    )));
+                }
+                ::std::result::Result::Ok(#ident {
+                    #(#idents: ::datalog::parse_column(#name, #columns, values[#positions])?,)*
+                })
+            }
+        }
+    })
+}
+
+// #[datalog(name = "edge")]
+fn relation_name(input: &DeriveInput) -> syn::Result<Option<String>> {
+    let mut name = None;
+    for attr in input.attrs.iter().filter(|a| a.path.is_ident("datalog")) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            other => return Err(syn::Error::new_spanned(other, "expected #[datalog(name = \"...\")]")),
+        };
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("name") => match nv.lit {
+                    Lit::Str(s) => name = Some(s.value()),
+                    other => return Err(syn::Error::new_spanned(other, "the name has to be a string")),
+                },
+                other => return Err(syn::Error::new_spanned(other, "the only option is name = \"...\"")),
+            }
+        }
+    }
+    Ok(name)
+}
+
+// SameGeneration -> same_generation
+fn snake_case(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn is_integer(ty: &Type) -> bool {
+    match ty {
+        Type::Path(p) => p.path.segments.last().is_some_and(|s| {
+            let s = s.ident.to_string();
+            [
+                "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64", "u128", "usize",
+            ]
+            .contains(&s.as_str())
+        }),
+        _ => false,
+    }
+}
+
+/*
+ * datalog! { ... } parses a program while the crate using it compiles. rust
+ * has already split the program into tokens by then, so it gets glued back
+ * into text the parser takes, remembering where each token came from so
+ * errors can point at it
+ */
+
+#[proc_macro]
+pub fn datalog(input: TokenStream) -> TokenStream {
+    match program(input.into()) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn program(input: Tokens) -> syn::Result<Tokens> {
+    let mut source = Source::default();
+    source.read(input);
+    let text = source.text.as_str();
+
+    let mut statements = vec![];
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let at = source.span_at(text.len() - rest.len());
+        let (next, statement) = parser::statement(rest).map_err(|_| {
+            syn::Error::new(at, "expected a fact, a rule, a constraint, .decl, .input, .output or .module here")
+        })?;
+        check(&statement).map_err(|e| syn::Error::new(at, e))?;
+        statements.push(statement);
+        rest = next.trim_start();
+    }
+
+    let canonical: Vec<String> = statements.iter().map(|s| s.to_string()).collect();
+    let canonical = canonical.join("\n");
+    Ok(quote!(::datalog::Program::checked(#canonical)))
+}
+
+// what the engine would refuse no matter what else is loaded
+fn check(statement: &Statement) -> Result<(), String> {
+    match statement {
+        Statement::Rule(r) => safety::check(r).map_err(|e| e.to_string()),
+        Statement::Constraint(c) => safety::check_constraint(c).map_err(|e| e.to_string()),
+        Statement::Fact(f) | Statement::Retract(f) => match f.vars.iter().find(|v| matches!(v, Variable::Free(_))) {
+            Some(Variable::Free(name)) => Err(format!("facts can't have free variables, found {}", name)),
+            _ => Ok(()),
+        },
+        Statement::Query(q) => Err(format!("{}? is a query, ask it with Engine::query", q)),
+        Statement::AsOf(_) => Err(format!("{} is a query, ask it with Engine::as_of", statement)),
+        Statement::Declaration(_) | Statement::Input(_) | Statement::Output(_) => Ok(()),
+        Statement::Module(m) => m.statements.iter().try_for_each(check),
+        Statement::Include(_) => Err(format!("{} can't be checked here, load the file with Engine::load_file", statement)),
+    }
+}
+
+#[derive(Default)]
+struct Source {
+    text: String,
+    // where each token starts in `text`
+    spans: Vec<(usize, Span)>,
+    // words (identifiers, literals) need a space between them, nothing else does
+    after_word: bool,
+}
+
+impl Source {
+    fn read(&mut self, tokens: Tokens) {
+        for token in tokens {
+            match token {
+                TokenTree::Group(g) => {
+                    let (open, close) = match g.delimiter() {
+                        Delimiter::Parenthesis => ("(", ")"),
+                        Delimiter::Bracket => ("[", "]"),
+                        Delimiter::Brace => ("{", "}"),
+                        Delimiter::None => ("", ""),
+                    };
+                    self.push(open, g.span_open(), false);
+                    self.read(g.stream());
+                    self.push(close, g.span_close(), false);
+                }
+                TokenTree::Ident(i) => self.push(&i.to_string(), i.span(), true),
+                TokenTree::Literal(l) => self.push(&l.to_string(), l.span(), true),
+                TokenTree::Punct(p) => self.push(&p.as_char().to_string(), p.span(), false),
+            }
+        }
+    }
+
+    fn push(&mut self, text: &str, span: Span, word: bool) {
+        if word && self.after_word {
+            self.text.push(' ');
+        }
+        self.spans.push((self.text.len(), span));
+        self.text.push_str(text);
+        self.after_word = word;
+    }
+
+    fn span_at(&self, offset: usize) -> Span {
+        self.spans
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= offset)
+            .map_or_else(Span::call_site, |(_, span)| *span)
+    }
+}
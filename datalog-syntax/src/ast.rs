@@ -0,0 +1,406 @@
+#![allow(unused_imports,dead_code)]
+
+use std::fmt;
+
+/*
+ * with the `serde` feature on, everything here (de)serializes. the json shape
+ * is part of the interface, changing it breaks whoever reads it:
+ *
+ *   variables   {"fixed": "a"}  {"free": "X"}
+ *   facts       {"name": "edge", "vars": [{"fixed": "a"}, {"free": "X"}]}
+ *   comparisons {"equals": false, "left": {"free": "X"}, "right": {"fixed": "a"}}
+ *   rule bodies [{"fact": {...}}, {"equals": {...}}, {"not": {...}},
+ *               {"count": {"result": {"free": "N"}, "over": {...}}}]
+ *   rules       {"head": {...}, "body": [...]}
+ *   constraints {"body": [...], "line": 3, "file": "graph.dl"}, no file when
+ *               it wasn't read from one
+ *   as of       {"query": {...}, "version": 3}
+ *   .decl       {"name": "edge", "columns": [{"name": "src", "kind": "symbol"}]}
+ *   directives  {"relation": "edge", "params": [["filename", "edge.csv"]]}
+ *   statements  {"rule": ...} {"fact": ...} {"query": ...} {"declaration": ...}
+ *               {"constraint": ...} {"input": ...} {"output": ...}
+ *               {"retract": ...} {"asof": ...} {"include": "graph.dl"}
+ *               {"module": {"name": "graph", "statements": [...]}}
+ *
+ * query answers are facts, so they come out the same way
+ */
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Variable {
+    Fixed(String),
+    Free(String),
+}
+
+impl Variable {
+    /// `_`, which matches anything and is a different variable every time
+    /// it's written
+    pub fn is_wildcard(&self) -> bool {
+        matches!(self, Variable::Free(name) if name == "_")
+    }
+}
+
+// like "x = Foo" in rule predicates
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EqualityConstraint {
+    pub equals: bool,
+    pub left: Variable,
+    pub right: Variable,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum BodyExpression {
+    Fact(Fact),
+    Equals(EqualityConstraint),
+    // !edge(X, Y), holds when there's no such fact
+    Not(Fact),
+    Count(Count),
+}
+
+impl BodyExpression {
+    /// every variable written in it, `_`s too
+    pub fn variables(&self) -> Vec<&Variable> {
+        match self {
+            BodyExpression::Fact(f) | BodyExpression::Not(f) => f.vars.iter().collect(),
+            BodyExpression::Equals(e) => vec![&e.left, &e.right],
+            BodyExpression::Count(c) => std::iter::once(&c.result).chain(&c.over.vars).collect(),
+        }
+    }
+}
+
+// N = count : { edge(X, _) }  how many facts match `over`, once for every value
+// of the variables it shares with the rest of the rule. the ones only it uses
+// are what gets counted
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Count {
+    pub result: Variable,
+    pub over: Fact,
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Fact {
+    pub name: String,
+    pub vars: Vec<Variable>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rule {
+    pub head: Fact,
+    pub body: Vec<BodyExpression>,
+}
+
+// :- manager(X, X).  a rule without a head, anything its body matches is a violation
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Constraint {
+    pub body: Vec<BodyExpression>,
+    /// the line it was written on, counting from 1
+    pub line: usize,
+    /// the file that line is in, None for programs that weren't read from
+    /// one, like what's typed at the REPL
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub file: Option<String>,
+}
+
+// path(a, X) as of 3?  a query on the facts and rules as they were at a version
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AsOf {
+    pub query: Fact,
+    pub version: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum ColumnType {
+    Symbol,
+    Number,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Column {
+    pub name: String,
+    pub kind: ColumnType,
+}
+
+// .decl edge(src: symbol, dst: symbol)
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Declaration {
+    pub name: String,
+    pub columns: Vec<Column>,
+}
+
+// .input edge(filename="edges.csv", delimiter=",")
+// the parameters are kept as written, whoever carries the directive out makes sense of them
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Directive {
+    pub relation: String,
+    pub params: Vec<(String, String)>,
+}
+
+impl Directive {
+    pub fn param(&self, key: &str) -> Option<&str> {
+        self.params.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+}
+
+// .module graph { node(a). path(X, Y) :- edge(X, Y). }
+// the relations made inside are graph::node and graph::path, see modules.rs
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Module {
+    pub name: String,
+    pub statements: Vec<Statement>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Statement {
+    Rule(Rule),
+    Fact(Fact),
+    // ~edge(a, b).
+    Retract(Fact),
+    Query(Fact),
+    AsOf(AsOf),
+    Constraint(Constraint),
+    Declaration(Declaration),
+    Input(Directive),
+    Output(Directive),
+    // .include "graph.dl"
+    Include(String),
+    Module(Module),
+}
+
+
+/*
+ * printing goes the other way from the parser: whatever these print parses
+ * back into the same value
+ */
+
+// constants that wouldn't parse back as themselves get quoted
+fn write_constant(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    let identifier = s.starts_with(|c: char| c.is_ascii_lowercase())
+        && s.chars().all(|c| c.is_alphanumeric() || c == '_');
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let number = !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit());
+    if identifier || number {
+        write!(f, "{}", s)
+    } else {
+        write_quoted(f, s)
+    }
+}
+
+fn write_quoted(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\t' => write!(f, "\\t")?,
+            '\n' => write!(f, "\\n")?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for Variable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Variable::Fixed(s) => write_constant(f, s),
+            Variable::Free(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl fmt::Display for EqualityConstraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = if self.equals { "=" } else { "!=" };
+        write!(f, "{} {} {}", self.left, op, self.right)
+    }
+}
+
+impl fmt::Display for BodyExpression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BodyExpression::Fact(fact) => write!(f, "{}", fact),
+            BodyExpression::Equals(e) => write!(f, "{}", e),
+            BodyExpression::Not(fact) => write!(f, "!{}", fact),
+            BodyExpression::Count(c) => write!(f, "{} = count : {{ {} }}", c.result, c.over),
+        }
+    }
+}
+
+// edge(a, b), without the `.` or `?` that makes it a statement
+impl fmt::Display for Fact {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}(", self.name)?;
+        for (i, v) in self.vars.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", v)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} :- ", self.head)?;
+        for (i, e) in self.body.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", e)?;
+        }
+        write!(f, ".")
+    }
+}
+
+// the line it came from isn't part of it, it prints the same wherever it was written
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, ":- ")?;
+        for (i, e) in self.body.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", e)?;
+        }
+        write!(f, ".")
+    }
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColumnType::Symbol => write!(f, "symbol"),
+            ColumnType::Number => write!(f, "number"),
+        }
+    }
+}
+
+impl fmt::Display for Declaration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, ".decl {}(", self.name)?;
+        for (i, c) in self.columns.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", c.name, c.kind)?;
+        }
+        write!(f, ")")
+    }
+}
+
+// edge(filename="edge.csv"), the keyword in front is up to the statement
+impl fmt::Display for Directive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.relation)?;
+        if self.params.is_empty() {
+            return Ok(());
+        }
+        write!(f, "(")?;
+        for (i, (key, value)) in self.params.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            // always quoted, `true` and `"true"` mean the same thing here
+            write!(f, "{}=", key)?;
+            write_quoted(f, value)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Statement::Rule(r) => write!(f, "{}", r),
+            Statement::Fact(fact) => write!(f, "{}.", fact),
+            Statement::Retract(fact) => write!(f, "~{}.", fact),
+            Statement::Query(q) => write!(f, "{}?", q),
+            Statement::AsOf(a) => write!(f, "{} as of {}?", a.query, a.version),
+            Statement::Constraint(c) => write!(f, "{}", c),
+            Statement::Declaration(d) => write!(f, "{}", d),
+            Statement::Input(d) => write!(f, ".input {}", d),
+            Statement::Output(d) => write!(f, ".output {}", d),
+            Statement::Include(file) => {
+                write!(f, ".include ")?;
+                write_quoted(f, file)
+            }
+            Statement::Module(m) => {
+                write!(f, ".module {} {{", m.name)?;
+                for s in &m.statements {
+                    write!(f, " {}", s)?;
+                }
+                write!(f, " }}")
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn test_ast_json_shape() {
+        let program = r#"
+            .decl edge(src: symbol, dst: symbol)
+            .input edge(filename="e.csv")
+            edge(a, "B c").
+            path(X, Y) :- edge(X, Y), X != a.
+            out(X, N) :- edge(X, _), N = count : { edge(X, _) }.
+            path(a, Y)?
+        "#;
+        let statements = parser::statements(program).unwrap().1;
+        let json = serde_json::to_value(&statements).unwrap();
+        assert_eq!(
+            serde_json::json!([
+                {"declaration": {"name": "edge", "columns": [
+                    {"name": "src", "kind": "symbol"},
+                    {"name": "dst", "kind": "symbol"},
+                ]}},
+                {"input": {"relation": "edge", "params": [["filename", "e.csv"]]}},
+                {"fact": {"name": "edge", "vars": [{"fixed": "a"}, {"fixed": "B c"}]}},
+                {"rule": {
+                    "head": {"name": "path", "vars": [{"free": "X"}, {"free": "Y"}]},
+                    "body": [
+                        {"fact": {"name": "edge", "vars": [{"free": "X"}, {"free": "Y"}]}},
+                        {"equals": {"equals": false, "left": {"free": "X"}, "right": {"fixed": "a"}}},
+                    ],
+                }},
+                {"rule": {
+                    "head": {"name": "out", "vars": [{"free": "X"}, {"free": "N"}]},
+                    "body": [
+                        {"fact": {"name": "edge", "vars": [{"free": "X"}, {"free": "_"}]}},
+                        {"count": {
+                            "result": {"free": "N"},
+                            "over": {"name": "edge", "vars": [{"free": "X"}, {"free": "_"}]},
+                        }},
+                    ],
+                }},
+                {"query": {"name": "path", "vars": [{"fixed": "a"}, {"free": "Y"}]}},
+            ]),
+            json
+        );
+        let back: Vec<Statement> = serde_json::from_value(json).unwrap();
+        assert_eq!(statements, back);
+    }
+}
@@ -0,0 +1,77 @@
+#![allow(unused_imports, dead_code)]
+
+use std::error;
+use std::fmt;
+
+/*
+ * the one error type everything returns, from the parser through the engine
+ * to the file formats. nothing a user types should be able to panic
+ */
+
+/// What went wrong, grouped by whose fault it was.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    /// the text isn't datalog. `line` counts from 1, `text` is the start of
+    /// where parsing gave up
+    Parse { line: usize, text: String },
+    /// a fact that doesn't fit its relation's `.decl`, a relation that can't
+    /// be changed, or a statement or directive that doesn't make sense where
+    /// it is
+    Schema(String),
+    /// a fact with variables in it, or a rule with a variable that no
+    /// positive body atom binds, which would make its answers infinite
+    Safety(String),
+    /// rules that negate a relation which depends on their own head, so
+    /// there's no order to evaluate them in
+    Stratification(String),
+    /// a fact or rule refused because it would make a constraint's body
+    /// match, or a constraint the database already breaks. says what matched
+    Constraint(String),
+    /// a query or rule that can't be answered as asked
+    Evaluation(String),
+    /// a file that can't be read or written, or holds something unreadable
+    Io { path: String, message: String },
+}
+
+impl Error {
+    pub fn io<E: fmt::Display>(path: &str, e: E) -> Error {
+        Error::Io {
+            path: path.to_string(),
+            message: e.to_string(),
+        }
+    }
+
+    /// The same error with `context` put in front of the message, like the
+    /// file and line a bad fact came from.
+    pub fn context(self, context: &str) -> Error {
+        let add = |m: String| format!("{}: {}", context, m);
+        match self {
+            Error::Parse { line, text } => Error::Parse { line, text },
+            Error::Schema(m) => Error::Schema(add(m)),
+            Error::Safety(m) => Error::Safety(add(m)),
+            Error::Stratification(m) => Error::Stratification(add(m)),
+            Error::Constraint(m) => Error::Constraint(add(m)),
+            Error::Evaluation(m) => Error::Evaluation(add(m)),
+            Error::Io { path, message } => Error::Io {
+                path,
+                message: add(message),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parse { line, text } => write!(f, "line {}: could not parse {:?}", line, text),
+            Error::Schema(m) => write!(f, "{}", m),
+            Error::Safety(m) => write!(f, "unsafe: {}", m),
+            Error::Stratification(m) => write!(f, "not stratifiable: {}", m),
+            Error::Constraint(m) => write!(f, "constraint violated: {}", m),
+            Error::Evaluation(m) => write!(f, "{}", m),
+            Error::Io { path, message } => write!(f, "{}: {}", path, message),
+        }
+    }
+}
+
+impl error::Error for Error {}
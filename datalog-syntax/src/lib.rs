@@ -0,0 +1,9 @@
+//! The parser, syntax tree and error type of the `datalog` crate, on their
+//! own so `datalog-derive` can check programs at compile time with the same
+//! code the engine runs. Use them through `datalog`.
+extern crate nom;
+
+pub mod ast;
+pub mod error;
+pub mod parser;
+pub mod safety;
@@ -0,0 +1,106 @@
+#![allow(unused_imports, dead_code)]
+
+use std::collections::HashSet;
+use std::fmt;
+
+/*
+ * which rules have finitely many answers. lives on its own so the datalog!
+ * macro can check rules at compile time with the same code the engine uses
+ */
+use crate::ast::{BodyExpression, Constraint, Rule, Variable, Variable::Fixed, Variable::Free};
+use crate::error::Error;
+
+/// A rule is safe when every variable in its head, in a negated atom or in a
+/// comparison is bound by a positive body atom, or by `=` to something that
+/// is. Anything else would range over every constant there is.
+///
+/// `_` never binds anything. In a negated atom it's fine, `!age(P, _)` holds
+/// when `P` has no age at all, anywhere else it's unbound.
+///
+/// `N = count : { edge(X, Y) }` binds `N`. The variables of `edge(X, Y)` that
+/// the rest of the rule uses too have to be bound like a negated atom's, the
+/// others are [`counted`].
+pub fn check(rule: &Rule) -> Result<(), Error> {
+    check_body(&rule.head.vars, &rule.body, rule)
+}
+
+/// The same as `check` for a constraint, which has no head.
+pub fn check_constraint(constraint: &Constraint) -> Result<(), Error> {
+    check_body(&[], &constraint.body, constraint)
+}
+
+/// The variables only the atom of a count uses. Those are what it counts,
+/// the rest it groups by. `_` isn't one, it's never bound at all.
+pub fn counted<'a>(head: &'a [Variable], body: &'a [BodyExpression]) -> HashSet<&'a str> {
+    let mut counted = HashSet::new();
+    for (i, expression) in body.iter().enumerate() {
+        if let BodyExpression::Count(c) = expression {
+            let elsewhere = |name: &str| {
+                head.iter().chain([&c.result]).filter_map(free).any(|v| v == name)
+                    || body.iter().enumerate().any(|(j, e)| j != i && e.variables().into_iter().filter_map(free).any(|v| v == name))
+            };
+            counted.extend(c.over.vars.iter().filter(|v| !v.is_wildcard()).filter_map(free).filter(|name| !elsewhere(name)));
+        }
+    }
+    counted
+}
+
+fn check_body(head: &[Variable], body: &[BodyExpression], shown: &dyn fmt::Display) -> Result<(), Error> {
+    let mut bound: HashSet<&str> = HashSet::new();
+    for expression in body {
+        match expression {
+            BodyExpression::Fact(f) => bound.extend(f.vars.iter().filter(|v| !v.is_wildcard()).filter_map(free)),
+            BodyExpression::Count(c) => bound.extend(free(&c.result).filter(|name| *name != "_")),
+            BodyExpression::Not(_) | BodyExpression::Equals(_) => {}
+        }
+    }
+    // `X = Y, Y = a` binds both, in whatever order they're written
+    let mut grew = true;
+    while grew {
+        grew = false;
+        for expression in body {
+            if let BodyExpression::Equals(e) = expression {
+                if !e.equals {
+                    continue;
+                }
+                let known = |v: &Variable| free(v).is_none_or(|name| bound.contains(name));
+                let unknown = match (known(&e.left), known(&e.right)) {
+                    (true, false) => free(&e.right),
+                    (false, true) => free(&e.left),
+                    _ => None,
+                };
+                if let Some(name) = unknown.filter(|name| *name != "_") {
+                    bound.insert(name);
+                    grew = true;
+                }
+            }
+        }
+    }
+
+    let counted = counted(head, body);
+    let mut used: Vec<&str> = head.iter().filter_map(free).collect();
+    for expression in body {
+        match expression {
+            BodyExpression::Fact(_) => {}
+            BodyExpression::Not(f) => used.extend(f.vars.iter().filter(|v| !v.is_wildcard()).filter_map(free)),
+            BodyExpression::Equals(e) => used.extend(free(&e.left).into_iter().chain(free(&e.right))),
+            BodyExpression::Count(c) => used.extend(
+                c.over.vars.iter().filter(|v| !v.is_wildcard()).filter_map(free).filter(|name| !counted.contains(name)),
+            ),
+        }
+    }
+    match used.into_iter().find(|name| !bound.contains(name)) {
+        Some(name) => Err(Error::Safety(format!(
+            "{} in {} isn't bound by a positive body atom",
+            name, shown
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn free(v: &Variable) -> Option<&str> {
+    match v {
+        Free(s) => Some(s),
+        Fixed(_) => None,
+    }
+}
@@ -0,0 +1,678 @@
+#![allow(unused_imports, dead_code)]
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/*
+ * the face the crate shows to other rust code. everything else stays
+ * private so the internals can keep changing underneath
+ */
+use crate::ast::{Column, ColumnType, Declaration, Fact, Statement, Variable};
+use crate::directives;
+use crate::engine::{DatalogEngine, RustEngine};
+use crate::error::Error;
+use crate::modules;
+use crate::parser;
+
+/// A Rust type that stands for the tuples of one relation, usually derived:
+///
+/// ```
+/// use datalog::{Engine, Relation};
+///
+/// #[derive(Relation, Debug, PartialEq)]
+/// struct Edge {
+///     src: String,
+///     dst: String,
+/// }
+///
+/// #[derive(Relation, Debug, PartialEq)]
+/// #[datalog(name = "path")]
+/// struct Reachable {
+///     from: String,
+///     to: String,
+/// }
+///
+/// let mut engine = Engine::new();
+/// engine.insert(Edge { src: "a".into(), dst: "b".into() })?;
+/// engine.insert(Edge { src: "b".into(), dst: "c".into() })?;
+/// engine.load_program("
+///     path(X, Y) :- edge(X, Y).
+///     path(X, Y) :- path(X, Z), edge(Z, Y).
+/// ")?;
+/// let mut from_a = engine.query_as::<Reachable>("path(a, X)")?;
+/// from_a.sort_by(|x, y| x.to.cmp(&y.to));
+/// assert_eq!("c", from_a[1].to);
+/// # Ok::<(), datalog::Error>(())
+/// ```
+///
+/// The derive names the relation after the struct in snake case (or
+/// `#[datalog(name = "...")]`), with one column per field in order. Integer
+/// fields are `number` columns, anything else is a `symbol` written out with
+/// `Display` and read back with `FromStr`.
+pub trait Relation: Sized {
+    const NAME: &'static str;
+    /// column names and types, in order
+    const COLUMNS: &'static [(&'static str, ColumnType)];
+
+    /// one value per column, the way they'd be written in a fact
+    fn values(&self) -> Vec<String>;
+    fn from_values(values: &[&str]) -> Result<Self, Error>;
+}
+
+// the `.decl` a relation type stands for
+fn declaration<R: Relation>() -> Declaration {
+    Declaration {
+        name: R::NAME.to_string(),
+        columns: R::COLUMNS
+            .iter()
+            .map(|(name, kind)| Column {
+                name: name.to_string(),
+                kind: *kind,
+            })
+            .collect(),
+    }
+}
+
+/// what `#[derive(Relation)]` reads each field back with
+#[doc(hidden)]
+pub fn parse_column<T: FromStr>(relation: &str, column: &str, value: &str) -> Result<T, Error>
+where
+    T::Err: fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|e: T::Err| Error::Schema(format!("{}.{}: {:?} {}", relation, column, value, e)))
+}
+
+/// A program `datalog!` already parsed and checked while compiling, so
+/// loading it can't fail on syntax or unsafe rules:
+///
+/// ```
+/// use datalog::datalog;
+///
+/// let reachable = datalog! {
+///     edge(a, b). edge(b, c).
+///     path(X, Y) :- edge(X, Y).
+///     path(X, Y) :- path(X, Z), edge(Z, Y).
+/// };
+/// let engine = reachable.engine()?;
+/// assert_eq!(2, engine.query("path(a, X)")?.count());
+/// # Ok::<(), datalog::Error>(())
+/// ```
+///
+/// Mistakes show up as compile errors pointing at the statement:
+///
+/// ```compile_fail
+/// let unsafe_rule = datalog::datalog! {
+///     path(X, Y) :- edge(X, Z).
+/// };
+/// ```
+///
+/// What depends on everything else loaded into an engine, like
+/// declarations and stratification, is still checked when it's loaded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Program {
+    text: &'static str,
+}
+
+impl Program {
+    /// what `datalog!` expands to, the text has to parse and be safe
+    #[doc(hidden)]
+    pub const fn checked(text: &'static str) -> Program {
+        Program { text }
+    }
+
+    /// the program written out one statement per line
+    pub fn as_str(&self) -> &'static str {
+        self.text
+    }
+
+    /// A new [`Engine`] with the program loaded.
+    pub fn engine(&self) -> Result<Engine, Error> {
+        let mut engine = Engine::new();
+        engine.load(self)?;
+        Ok(engine)
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.text)
+    }
+}
+
+/// Sets up an [`Engine`].
+///
+/// ```
+/// let engine = datalog::Engine::builder().threads(4).build();
+/// ```
+#[derive(Debug, Default)]
+pub struct EngineBuilder {
+    threads: usize,
+    written_join_order: bool,
+    well_founded: bool,
+}
+
+impl EngineBuilder {
+    /// How many threads rules get evaluated on, the default of 0 (or 1)
+    /// keeps everything on the calling thread.
+    pub fn threads(mut self, threads: usize) -> EngineBuilder {
+        self.threads = threads;
+        self
+    }
+
+    /// Join rule bodies in the order they're written instead of letting the
+    /// planner reorder them. Answers are the same either way.
+    pub fn written_join_order(mut self, pinned: bool) -> EngineBuilder {
+        self.written_join_order = pinned;
+        self
+    }
+
+    /// Accept rules that negate their way around a cycle, like
+    /// `win(X) :- move(X, Y), !win(Y).`, and evaluate them under the
+    /// well-founded semantics. Some answers can then be neither true nor
+    /// false, see [`Answer::is_undefined`].
+    ///
+    /// ```
+    /// let mut engine = datalog::Engine::builder().well_founded(true).build();
+    /// engine.load_program("
+    ///     move(a, b). move(b, a). move(b, c). move(c, d).
+    ///     win(X) :- move(X, Y), !win(Y).
+    /// ")?;
+    /// for answer in engine.query("win(X)")? {
+    ///     match &answer["X"] {
+    ///         "c" => assert!(!answer.is_undefined()),
+    ///         _ => assert!(answer.is_undefined()),
+    ///     }
+    /// }
+    /// # Ok::<(), datalog::Error>(())
+    /// ```
+    pub fn well_founded(mut self, on: bool) -> EngineBuilder {
+        self.well_founded = on;
+        self
+    }
+
+    pub fn build(self) -> Engine {
+        let mut inner = RustEngine::new();
+        inner.set_threads(self.threads);
+        inner.pin_written_join_order(self.written_join_order);
+        inner.set_well_founded(self.well_founded);
+        Engine { inner }
+    }
+}
+
+/// A datalog database: facts, the rules that derive more of them, and
+/// queries over both.
+///
+/// ```
+/// use datalog::Engine;
+///
+/// let mut engine = Engine::new();
+/// engine.load_program("
+///     edge(a, b). edge(b, c).
+///     path(X, Y) :- edge(X, Y).
+///     path(X, Y) :- path(X, Z), edge(Z, Y).
+/// ")?;
+/// engine.add_fact("edge", &["c", "d"])?;
+///
+/// let mut reachable: Vec<String> = engine
+///     .query("path(a, X)")?
+///     .map(|answer| answer["X"].to_string())
+///     .collect();
+/// reachable.sort();
+/// assert_eq!(vec!["b", "c", "d"], reachable);
+/// # Ok::<(), datalog::Error>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct Engine {
+    inner: RustEngine,
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        Engine::default()
+    }
+
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::default()
+    }
+
+    /// Runs every declaration, fact, rule and `.input`/`.output` in
+    /// `program`. Files named by directives and `.include`s are taken
+    /// relative to the current directory. Queries don't belong in a program,
+    /// ask them with [`Engine::query`].
+    ///
+    /// Relations made inside a `.module` block are named after it, and the
+    /// names used in there mean the module's own relations first:
+    ///
+    /// ```
+    /// # use datalog::Engine;
+    /// let mut engine = Engine::new();
+    /// engine.load_program("
+    ///     node(top).
+    ///     .module graph {
+    ///         edge(a, b).
+    ///         node(X) :- edge(X, _).
+    ///     }
+    /// ")?;
+    /// assert_eq!(1, engine.query("graph::node(X)")?.count());
+    /// assert_eq!("top", &engine.query("node(X)")?.next().unwrap()["X"]);
+    /// # Ok::<(), datalog::Error>(())
+    /// ```
+    ///
+    /// Statements before one that fails stay loaded. Constraints are checked
+    /// once the whole program is in, and if it breaks one none of it stays.
+    pub fn load_program(&mut self, program: &str) -> Result<(), Error> {
+        self.run(program, Path::new(""), None)
+    }
+
+    /// Loads a program checked by `datalog!`, see [`Program`].
+    pub fn load(&mut self, program: &Program) -> Result<(), Error> {
+        self.load_program(program.text)
+    }
+
+    /// Like [`Engine::load_program`] with the contents of a `.dl` file, with
+    /// directives and `.include`s taken relative to the file's directory.
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let program = std::fs::read_to_string(path).map_err(|e| Error::io(&path.display().to_string(), e))?;
+        self.run(&program, path.parent().unwrap_or_else(|| Path::new("")), Some(&path.display().to_string()))
+    }
+
+    // `file` is where `program` was read from, if anywhere
+    fn run(&mut self, program: &str, dir: &Path, file: Option<&str>) -> Result<(), Error> {
+        let (rest, mut statements) = parser::statements(program).map_err(|_| parser::parse_error(program, program))?;
+        if let Some(file) = file {
+            modules::locate(&mut statements, file);
+        }
+        let statements = modules::resolve(statements, dir, &|name| self.inner.defines(name))?;
+        // constraints get checked once at the end, not after every fact
+        self.inner.batch(|inner| run_statements(inner, statements, dir))??;
+        if !rest.trim().is_empty() {
+            return Err(parser::parse_error(program, rest));
+        }
+        Ok(())
+    }
+
+    /// Stores one fact, `Ok(false)` means it was already known.
+    pub fn add_fact(&mut self, relation: &str, values: &[&str]) -> Result<bool, Error> {
+        let fact = Fact {
+            name: relation.to_string(),
+            vars: values.iter().map(|v| Variable::Fixed(v.to_string())).collect(),
+        };
+        self.inner.push_fact(fact)
+    }
+
+    /// Takes a stored fact back out, `Ok(false)` means it wasn't there.
+    pub fn retract_fact(&mut self, relation: &str, values: &[&str]) -> Result<bool, Error> {
+        let fact = Fact {
+            name: relation.to_string(),
+            vars: values.iter().map(|v| Variable::Fixed(v.to_string())).collect(),
+        };
+        self.inner.retract_fact(fact)
+    }
+
+    /// How many times the stored facts have changed, see [`Engine::as_of`].
+    pub fn version(&self) -> u64 {
+        self.inner.version()
+    }
+
+    /// The database as it was at `version`, to query the way it was back
+    /// then. Every fact stored or retracted makes a new version, and
+    /// everything one transaction changes shares one. Rules count from the
+    /// version that was current when they were added, or that their
+    /// transaction committed as, so a rule added since isn't used.
+    ///
+    /// ```
+    /// use datalog::Engine;
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.load_program("access(ann, payroll). access(bob, payroll).")?;
+    /// let tuesday = engine.version();
+    /// engine.retract_fact("access", &["bob", "payroll"])?;
+    ///
+    /// assert_eq!(1, engine.query("access(U, payroll)")?.count());
+    /// assert_eq!(2, engine.as_of(tuesday)?.query("access(U, payroll)")?.count());
+    /// # Ok::<(), datalog::Error>(())
+    /// ```
+    pub fn as_of(&self, version: u64) -> Result<Engine, Error> {
+        Ok(Engine {
+            inner: self.inner.as_of(version)?,
+        })
+    }
+
+    /// Stores one tuple of a [`Relation`], declaring the relation the first
+    /// time. `Ok(false)` means it was already known.
+    pub fn insert<R: Relation>(&mut self, row: R) -> Result<bool, Error> {
+        self.inner.declare(declaration::<R>())?;
+        let fact = Fact {
+            name: R::NAME.to_string(),
+            vars: row.values().into_iter().map(Variable::Fixed).collect(),
+        };
+        self.inner.push_fact(fact)
+    }
+
+    /// Starts a transaction: what gets loaded from here on stays provisional
+    /// until [`Engine::commit`], and constraints aren't checked until then.
+    ///
+    /// ```
+    /// use datalog::Engine;
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.load_program(":- manages(X, X).")?;
+    /// engine.begin()?;
+    /// engine.load_program("manages(ann, bob). manages(bob, bob).")?;
+    /// assert!(engine.commit().is_err());
+    /// assert_eq!(0, engine.query("manages(X, Y)")?.count());
+    /// # Ok::<(), datalog::Error>(())
+    /// ```
+    pub fn begin(&mut self) -> Result<(), Error> {
+        self.inner.begin()
+    }
+
+    /// Keeps everything loaded since [`Engine::begin`]. If that breaks a
+    /// constraint it's all rolled back instead, and the error says what broke.
+    pub fn commit(&mut self) -> Result<(), Error> {
+        self.inner.commit()
+    }
+
+    /// Forgets everything loaded since [`Engine::begin`].
+    pub fn rollback(&mut self) -> Result<(), Error> {
+        self.inner.rollback()
+    }
+
+    /// Like [`Engine::query`] for a query on `R`'s relation, with every answer
+    /// read back as an `R`. Only true answers, never undefined ones.
+    pub fn query_as<R: Relation>(&self, query: &str) -> Result<Vec<R>, Error> {
+        let q = parser::query(query)?;
+        if q.name != R::NAME || q.vars.len() != R::COLUMNS.len() {
+            return Err(Error::Schema(format!(
+                "{}? isn't a query on {}, which has {} columns",
+                q,
+                R::NAME,
+                R::COLUMNS.len()
+            )));
+        }
+        let mut rows = vec![];
+        for fact in self.inner.query(q)?.unwrap_or_default() {
+            let values: Vec<&str> = fact
+                .vars
+                .iter()
+                .map(|v| match v {
+                    Variable::Fixed(s) | Variable::Free(s) => s.as_str(),
+                })
+                .collect();
+            rows.push(R::from_values(&values)?);
+        }
+        Ok(rows)
+    }
+
+    /// Answers a query like `path(a, X)` (the `?` is optional) with one
+    /// [`Answer`] per way its variables can be filled in. A query without
+    /// variables gets a single empty answer when it holds, none when it doesn't.
+    pub fn query(&self, query: &str) -> Result<Answers, Error> {
+        let q = parser::query(query)?;
+        let mut vars: Vec<String> = vec![];
+        let mut columns = vec![];
+        for (i, v) in q.vars.iter().enumerate() {
+            if let Variable::Free(name) = v {
+                if !v.is_wildcard() && !vars.contains(name) {
+                    vars.push(name.clone());
+                    columns.push(i);
+                }
+            }
+        }
+        let (answers, undefined) = self.inner.query_with_undefined(q)?.unwrap_or_default();
+        let answers: Vec<(Fact, bool)> = answers
+            .into_iter()
+            .map(|a| (a, false))
+            .chain(undefined.into_iter().map(|a| (a, true)))
+            .collect();
+        Ok(Answers {
+            vars,
+            columns,
+            facts: answers.into_iter(),
+        })
+    }
+}
+
+// what `Engine::run` does with each statement, inside one batch
+fn run_statements(inner: &mut RustEngine, statements: Vec<Statement>, dir: &Path) -> Result<(), Error> {
+    for statement in statements {
+        match statement {
+            Statement::Fact(f) => {
+                inner.push_fact(f)?;
+            }
+            Statement::Rule(r) => inner.push_rule(r)?,
+            Statement::Constraint(c) => inner.push_constraint(c)?,
+            Statement::Declaration(d) => inner.declare(d)?,
+            Statement::Input(d) => {
+                directives::input(inner, dir, &d)?;
+            }
+            Statement::Output(d) => {
+                directives::output(inner, dir, &d)?;
+            }
+            Statement::Retract(f) => {
+                inner.retract_fact(f)?;
+            }
+            Statement::Query(q) => {
+                return Err(Error::Schema(format!("{}? is a query, ask it with Engine::query", q)))
+            }
+            Statement::AsOf(a) => {
+                return Err(Error::Schema(format!("{} is a query, ask it with Engine::as_of", Statement::AsOf(a))))
+            }
+            Statement::Include(_) | Statement::Module(_) => unreachable!("modules::resolve leaves none behind"),
+        }
+    }
+    Ok(())
+}
+
+/// The answers to a query, see [`Engine::query`].
+#[derive(Debug)]
+pub struct Answers {
+    vars: Vec<String>,
+    // where each variable first shows up in the query
+    columns: Vec<usize>,
+    // and whether each one is undefined
+    facts: std::vec::IntoIter<(Fact, bool)>,
+}
+
+impl Iterator for Answers {
+    type Item = Answer;
+
+    fn next(&mut self) -> Option<Answer> {
+        let (fact, undefined) = self.facts.next()?;
+        let values = self
+            .columns
+            .iter()
+            .map(|i| match &fact.vars[*i] {
+                Variable::Fixed(s) | Variable::Free(s) => s.clone(),
+            })
+            .collect();
+        Some(Answer {
+            vars: self.vars.clone(),
+            values,
+            undefined,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.facts.size_hint()
+    }
+}
+
+/// What a query's variables are bound to in one answer.
+///
+/// ```
+/// let mut engine = datalog::Engine::new();
+/// engine.load_program("age(alice, 30).")?;
+/// let answer = engine.query("age(Who, Years)")?.next().unwrap();
+/// assert_eq!(Some("alice"), answer.get("Who"));
+/// assert_eq!(30, answer.parse::<u32>("Years")?);
+/// # Ok::<(), datalog::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Answer {
+    vars: Vec<String>,
+    values: Vec<String>,
+    undefined: bool,
+}
+
+impl Answer {
+    /// Whether the answer is neither true nor false, which only happens
+    /// with [`EngineBuilder::well_founded`] on. Undefined answers come after
+    /// the true ones.
+    pub fn is_undefined(&self) -> bool {
+        self.undefined
+    }
+
+    pub fn get(&self, var: &str) -> Option<&str> {
+        let i = self.vars.iter().position(|v| v == var)?;
+        Some(&self.values[i])
+    }
+
+    /// The value bound to `var` turned into a `T`, for numbers and the like.
+    pub fn parse<T: FromStr>(&self, var: &str) -> Result<T, Error>
+    where
+        T::Err: fmt::Display,
+    {
+        let value = self
+            .get(var)
+            .ok_or_else(|| Error::Evaluation(format!("{} isn't in the query", var)))?;
+        value
+            .parse()
+            .map_err(|e: T::Err| Error::Evaluation(format!("{}={:?}: {}", var, value, e)))
+    }
+
+    /// `(variable, value)` in the order the variables first show up in the query
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars.iter().map(|v| v.as_str()).zip(self.values.iter().map(|v| v.as_str()))
+    }
+}
+
+impl std::ops::Index<&str> for Answer {
+    type Output = str;
+
+    /// panics when `var` isn't one of the query's variables, see [`Answer::get`]
+    fn index(&self, var: &str) -> &str {
+        match self.get(var) {
+            Some(value) => value,
+            None => panic!("{} isn't in the query", var),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeated_variables_bind_once() {
+        let mut e = Engine::new();
+        e.load_program("edge(a, a). edge(a, b).").unwrap();
+        let answers: Vec<Answer> = e.query("edge(X, X)?").unwrap().collect();
+        assert_eq!(1, answers.len());
+        assert_eq!(vec![("X", "a")], answers[0].iter().collect::<Vec<_>>());
+    }
+
+    #[derive(crate::Relation, Debug, PartialEq)]
+    struct Person {
+        name: String,
+        age: u32,
+    }
+
+    #[test]
+    fn test_derived_relation() {
+        assert_eq!("person", Person::NAME);
+        assert_eq!(ColumnType::Number, Person::COLUMNS[1].1);
+
+        let mut e = Engine::new();
+        let alice = || Person { name: "Alice Smith".to_string(), age: 30 };
+        assert_eq!(Ok(true), e.insert(alice()));
+        assert_eq!(Ok(false), e.insert(alice()));
+        e.load_program("person(bob, 41). adult(N) :- person(N, A), A != 7.").unwrap();
+
+        let mut people = e.query_as::<Person>("person(N, A)").unwrap();
+        people.sort_by_key(|p| p.age);
+        assert_eq!(alice(), people[0]);
+        assert_eq!(41, people[1].age);
+        assert_eq!(Ok(vec![alice()]), e.query_as::<Person>("person(\"Alice Smith\", A)"));
+
+        // the declaration came along, so ages have to be numbers
+        assert!(e.load_program("person(carol, old).").is_err());
+        assert!(e.query_as::<Person>("adult(N)").is_err());
+    }
+
+    #[test]
+    fn test_checked_program() {
+        let program = crate::datalog! {
+            .decl age(name: symbol, years: number)
+            age(alice, 30). age("Bob Jones", -4).
+            adult(N) :- age(N, A), !minor(N).
+            minor(N) :- age(N, A), A != 30.
+        };
+        assert_eq!("age(alice, 30).", program.as_str().lines().nth(1).unwrap());
+        let e = program.engine().unwrap();
+        let adults: Vec<Answer> = e.query("adult(N)").unwrap().collect();
+        assert_eq!(1, adults.len());
+        assert_eq!("alice", &adults[0]["N"]);
+
+        // parses back to the same thing
+        assert_eq!(program.as_str(), crate::pretty::program(program.as_str()).unwrap().trim_end());
+    }
+
+    #[test]
+    fn test_modules_across_loads() {
+        let mut e = Engine::new();
+        e.load_program(".module hr { reports(ann, bob). }").unwrap();
+        // reports is hr's own from before, boss is made here
+        e.load_program(".module hr { boss(X) :- reports(_, X). } reports(carl, dee).").unwrap();
+        let bosses: Vec<Answer> = e.query("hr::boss(X)").unwrap().collect();
+        assert_eq!(1, bosses.len());
+        assert_eq!("bob", &bosses[0]["X"]);
+        assert_eq!(1, e.query("reports(X, Y)").unwrap().count());
+    }
+
+    #[test]
+    fn test_constraints_are_checked_once_per_load() {
+        let mut e = Engine::new();
+        e.load_program(":- path(X, X). path(X, Y) :- edge(X, Y). path(X, Z) :- path(X, Y), edge(Y, Z).")
+            .unwrap();
+        let chain: String = (0..100).map(|i| format!("edge(n{}, n{}). ", i, i + 1)).collect();
+        e.load_program(&chain).unwrap();
+        assert_eq!(100, e.query("path(n0, X)").unwrap().count());
+
+        // closing the loop breaks it, and none of the program stays
+        let err = e.load_program("edge(m0, m1). edge(n100, n0).").unwrap_err().to_string();
+        assert!(err.starts_with("constraint violated: :- path(X, X). by path(n0, n0)"), "{}", err);
+        assert_eq!(0, e.query("edge(m0, X)").unwrap().count());
+
+        // from a file it says where the constraint is
+        let mut e = Engine::new();
+        let file = std::env::temp_dir().join(format!("datalog-located-{}.dl", std::process::id()));
+        std::fs::write(&file, "edge(a, a).\n:- edge(X, X).\n").unwrap();
+        let err = e.load_file(&file).unwrap_err().to_string();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(
+            format!("constraint violated: :- edge(X, X). at {}:2", file.display()),
+            err.split(" by ").next().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut e = Engine::new();
+        assert_eq!(
+            Err(Error::Parse { line: 2, text: "edge(b, .".to_string() }),
+            e.load_program("edge(a, b).\nedge(b, .")
+        );
+        assert!(e.load_program("edge(a, X).").is_err());
+        assert!(e.load_program("edge(a, X)?").is_err());
+        assert!(e.query("edge(a").is_err());
+        assert!(e.query("edge(a, b)").unwrap().next().is_some());
+        let answer = e.query("edge(a, X)").unwrap().next().unwrap();
+        assert!(answer.parse::<i32>("X").is_err());
+        assert!(answer.parse::<i32>("Y").is_err());
+    }
+}
@@ -0,0 +1,346 @@
+#![allow(unused_imports, dead_code)]
+extern crate csv;
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/*
+ * loads relations from csv/tsv files and writes them back out, souffle style:
+ *
+ *   .decl edge(src: symbol, dst: symbol)
+ *   .input edge(filename="edges.csv", delimiter=",", headers=true)
+ *   .output path
+ */
+use crate::ast::{ColumnType, Declaration, Directive, Fact, Variable};
+use crate::engine::DatalogEngine;
+use crate::error::Error;
+
+/// How a relation is laid out in a delimited file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DelimitedOptions {
+    pub path: PathBuf,
+    pub delimiter: u8,
+    /// the first line names the columns instead of holding a tuple
+    pub headers: bool,
+}
+
+impl DelimitedOptions {
+    /// Reads the parameters of an `.input` directive. Like souffle the file
+    /// defaults to `<relation>.facts`, tab separated, no header line.
+    pub fn input(d: &Directive) -> Result<DelimitedOptions, Error> {
+        DelimitedOptions::from_directive(d, format!("{}.facts", d.relation), b'\t')
+    }
+
+    /// Reads the parameters of an `.output` directive, which defaults to
+    /// `<relation>.csv`, still tab separated.
+    pub fn output(d: &Directive) -> Result<DelimitedOptions, Error> {
+        DelimitedOptions::from_directive(d, format!("{}.csv", d.relation), b'\t')
+    }
+
+    fn from_directive(d: &Directive, path: String, delimiter: u8) -> Result<DelimitedOptions, Error> {
+        let mut opts = DelimitedOptions {
+            path: PathBuf::from(path),
+            delimiter,
+            headers: false,
+        };
+        // an explicit delimiter wins over the one the format implies
+        match d.param("format") {
+            None | Some("tsv") => {}
+            Some("csv") => opts.delimiter = b',',
+            Some(other) => return Err(Error::Schema(format!("{} isn't a delimited format", other))),
+        }
+        for (key, value) in &d.params {
+            match key.as_str() {
+                "filename" => opts.path = PathBuf::from(value),
+                "format" => {}
+                "delimiter" => match value.as_bytes() {
+                    [b] => opts.delimiter = *b,
+                    _ => return Err(Error::Schema(format!("delimiter has to be one character, got {:?}", value))),
+                },
+                "headers" => match value.as_str() {
+                    "true" => opts.headers = true,
+                    "false" => opts.headers = false,
+                    _ => return Err(Error::Schema(format!("headers is true or false, got {:?}", value))),
+                },
+                _ => return Err(Error::Schema(format!("unknown parameter {} for {}", key, d.relation))),
+            }
+        }
+        Ok(opts)
+    }
+
+    /// relative paths are taken relative to `dir`, like the directory of the program file
+    pub fn relative_to(mut self, dir: &Path) -> DelimitedOptions {
+        if self.path.is_relative() {
+            self.path = dir.join(&self.path);
+        }
+        self
+    }
+}
+
+/// Loads every line of the file in `opts` as a fact of `relation`, returning
+/// how many new facts that made.
+pub fn import(engine: &mut dyn DatalogEngine, relation: &str, opts: &DelimitedOptions) -> Result<usize, Error> {
+    let file = File::open(&opts.path).map_err(|e| Error::io(&opts.path.display().to_string(), e))?;
+    import_from(engine, relation, file, &opts.path.display().to_string(), opts)
+}
+
+/// Like `import` but from anything readable. `source` is only used to say
+/// where a bad line came from.
+///
+/// Every line needs as many fields as the header line, or as the `.decl`
+/// has columns, or failing both as the first line. When the relation has a
+/// `.decl`, number columns have to hold integers, and with `headers` set the
+/// file's columns are matched to the declared ones by name, so their order in
+/// the file doesn't matter.
+pub fn import_from<R: Read>(
+    engine: &mut dyn DatalogEngine,
+    relation: &str,
+    input: R,
+    source: &str,
+    opts: &DelimitedOptions,
+) -> Result<usize, Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(opts.delimiter)
+        .has_headers(opts.headers)
+        .flexible(true)
+        .from_reader(input);
+    let decl = engine.declaration(relation).cloned();
+
+    // which field of a line goes in each column of the relation
+    let mut fields: Option<Vec<usize>> = None;
+    if let (true, Some(decl)) = (opts.headers, &decl) {
+        let headers = reader.headers().map_err(|e| Error::io(source, e))?.clone();
+        let mut picked = vec![];
+        for column in &decl.columns {
+            match headers.iter().position(|h| h.trim() == column.name) {
+                Some(i) => picked.push(i),
+                None => {
+                    let e = Error::Schema(format!("no {} column in the header", column.name));
+                    return Err(e.context(&format!("{}:1", source)));
+                }
+            }
+        }
+        fields = Some(picked);
+    }
+    // how many fields every line needs: as many as the header has, or the
+    // .decl's columns, or else however many the first line has
+    let mut width = match (opts.headers, &decl) {
+        (true, _) => Some(reader.headers().map_err(|e| Error::io(source, e))?.len()),
+        (false, Some(decl)) => Some(decl.columns.len()),
+        (false, None) => None,
+    };
+
+    let mut added = 0;
+    for record in reader.records() {
+        let record = record.map_err(|e| Error::io(source, e))?;
+        let line = record.position().map_or(0, |p| p.line());
+        let expected = *width.get_or_insert(record.len());
+        if record.len() != expected {
+            let e = Error::Schema(format!("expected {} fields, found {}", expected, record.len()));
+            return Err(e.context(&format!("{}:{}", source, line)));
+        }
+        let values: Vec<&str> = match &fields {
+            Some(picked) => picked.iter().map(|i| &record[*i]).collect(),
+            None => record.iter().collect(),
+        };
+        let mut vars = Vec::with_capacity(values.len());
+        for (i, value) in values.iter().enumerate() {
+            let kind = decl
+                .as_ref()
+                .and_then(|d| d.columns.get(i))
+                .map_or(ColumnType::Symbol, |c| c.kind);
+            vars.push(Variable::Fixed(match kind {
+                ColumnType::Symbol => value.to_string(),
+                // `007` and `7` are the same number, store them the same way
+                ColumnType::Number => match value.trim().parse::<i64>() {
+                    Ok(n) => n.to_string(),
+                    Err(_) => {
+                        let e = Error::Schema(format!("{:?} is not a number", value));
+                        return Err(e.context(&format!("{}:{}", source, line)));
+                    }
+                },
+            }));
+        }
+        let fact = Fact {
+            name: relation.to_string(),
+            vars,
+        };
+        if engine.push_fact(fact).map_err(|e| e.context(&format!("{}:{}", source, line)))? {
+            added += 1;
+        }
+    }
+    Ok(added)
+}
+
+/// Writes every tuple of `relation`, stored or derived, to the file in
+/// `opts`, returning how many were written.
+pub fn export(engine: &dyn DatalogEngine, relation: &str, opts: &DelimitedOptions) -> Result<usize, Error> {
+    let file = File::create(&opts.path).map_err(|e| Error::io(&opts.path.display().to_string(), e))?;
+    export_to(engine, relation, file, opts)
+}
+
+/// Like `export` but to anything writable. The relation needs a `.decl`,
+/// that's where the number of columns (and the header line) comes from.
+pub fn export_to<W: Write>(
+    engine: &dyn DatalogEngine,
+    relation: &str,
+    output: W,
+    opts: &DelimitedOptions,
+) -> Result<usize, Error> {
+    let decl = match engine.declaration(relation) {
+        Some(d) => d,
+        None => return Err(Error::Schema(format!("declare {} with .decl before writing it out", relation))),
+    };
+    let query = Fact {
+        name: relation.to_string(),
+        vars: (0..decl.columns.len())
+            .map(|i| Variable::Free(format!("C{}", i)))
+            .collect(),
+    };
+    let mut rows: Vec<Vec<String>> = engine
+        .query(query)?
+        .unwrap_or_default()
+        .into_iter()
+        .map(|f| {
+            f.vars
+                .into_iter()
+                .map(|v| match v {
+                    Variable::Fixed(s) | Variable::Free(s) => s,
+                })
+                .collect()
+        })
+        .collect();
+    // same file for the same facts, whatever order they were derived in
+    rows.sort();
+
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(opts.delimiter)
+        .from_writer(output);
+    let path = opts.path.display().to_string();
+    let fail = |e: csv::Error| Error::io(&path, e);
+    if opts.headers {
+        writer
+            .write_record(decl.columns.iter().map(|c| c.name.as_str()))
+            .map_err(fail)?;
+    }
+    for row in &rows {
+        writer.write_record(row).map_err(fail)?;
+    }
+    writer.flush().map_err(|e| Error::io(&path, e))?;
+    Ok(rows.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Statement;
+    use crate::engine::RustEngine;
+    use crate::parser;
+
+    fn run(e: &mut dyn DatalogEngine, program: &str) {
+        let (rest, statements) = parser::statements(program).unwrap();
+        assert_eq!("", rest);
+        for s in statements {
+            match s {
+                Statement::Fact(f) => {
+                    e.push_fact(f).unwrap();
+                }
+                Statement::Rule(r) => e.push_rule(r).unwrap(),
+                Statement::Declaration(d) => e.declare(d).unwrap(),
+                x => panic!("not in test programs {:?}", x),
+            }
+        }
+    }
+
+    fn options(directive: &str) -> DelimitedOptions {
+        match parser::statement(directive) {
+            Ok((_, Statement::Input(d))) => DelimitedOptions::input(&d).unwrap(),
+            Ok((_, Statement::Output(d))) => DelimitedOptions::output(&d).unwrap(),
+            x => panic!("not a directive {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_directive_defaults() {
+        let input = options(".input edge");
+        assert_eq!(PathBuf::from("edge.facts"), input.path);
+        assert_eq!(b'\t', input.delimiter);
+        assert!(!input.headers);
+
+        let output = options(r#".output path(filename="out.csv", delimiter=",", headers=true)"#);
+        assert_eq!(PathBuf::from("out.csv"), output.path);
+        assert_eq!(b',', output.delimiter);
+        assert!(output.headers);
+    }
+
+    #[test]
+    fn test_import_maps_headers_to_declared_columns() {
+        let mut e = RustEngine::new();
+        run(&mut e, ".decl person(name: symbol, age: number)");
+        let file = "age,name,city\n042,\"Smith, Alice\",nyc\n7,bob,sf\n";
+        let opts = options(r#".input person(delimiter=",", headers=true)"#);
+        assert_eq!(Ok(2), import_from(&mut e, "person", file.as_bytes(), "people.csv", &opts));
+
+        let q = match parser::statement("person(N, 42)?") {
+            Ok((_, Statement::Query(q))) => q,
+            x => panic!("not a query {:?}", x),
+        };
+        let answers = e.query(q).unwrap().unwrap();
+        assert_eq!(vec![Variable::Fixed("Smith, Alice".to_string()), Variable::Fixed("42".to_string())], answers[0].vars);
+
+        assert_eq!(
+            Err(Error::Schema("people.csv:1: no age column in the header".to_string())),
+            import_from(&mut e, "person", "name,city\nbob,sf\n".as_bytes(), "people.csv", &opts)
+        );
+    }
+
+    #[test]
+    fn test_import_reports_the_bad_line() {
+        let mut e = RustEngine::new();
+        run(&mut e, ".decl person(name: symbol, age: number)");
+        let file = "alice\t30\nbob\tthirty\n";
+        assert_eq!(
+            Err(Error::Schema(r#"people.tsv:2: "thirty" is not a number"#.to_string())),
+            import_from(&mut e, "person", file.as_bytes(), "people.tsv", &options(".input person"))
+        );
+    }
+
+    #[test]
+    fn test_import_refuses_lines_of_the_wrong_width() {
+        let mut e = RustEngine::new();
+        run(&mut e, ".decl person(name: symbol, age: number)");
+        let opts = options(".input person");
+        assert_eq!(
+            Err(Error::Schema("people.tsv:2: expected 2 fields, found 3".to_string())),
+            import_from(&mut e, "person", "alice\t30\nbob\t40\tsf\n".as_bytes(), "people.tsv", &opts)
+        );
+        let opts = options(r#".input person(delimiter=",", headers=true)"#);
+        assert_eq!(
+            Err(Error::Schema("people.csv:3: expected 3 fields, found 1".to_string())),
+            import_from(&mut e, "person", "age,name,city\n7,bob,sf\n8\n".as_bytes(), "people.csv", &opts)
+        );
+        // without a .decl the first line says how wide the rest are
+        assert_eq!(
+            Err(Error::Schema("edges.tsv:3: expected 2 fields, found 1".to_string())),
+            import_from(&mut e, "edge", "a\tb\nb\tc\nc\n".as_bytes(), "edges.tsv", &options(".input edge"))
+        );
+    }
+
+    #[test]
+    fn test_export_derived_relation() {
+        let mut e = RustEngine::new();
+        run(&mut e, "
+            .decl path(from: symbol, to: symbol)
+            edge(a, b). edge(b, \"c d\").
+            path(X, Y) :- edge(X, Y).
+            path(X, Y) :- path(X, Z), edge(Z, Y).
+        ");
+        let mut out = vec![];
+        let opts = options(r#".output path(delimiter=",", headers=true)"#);
+        assert_eq!(Ok(3), export_to(&e, "path", &mut out, &opts));
+        assert_eq!("from,to\na,b\na,c d\nb,c d\n", String::from_utf8(out).unwrap());
+
+        assert!(export_to(&e, "edge", vec![], &opts).is_err());
+    }
+}
@@ -0,0 +1,236 @@
+#![allow(unused_imports, dead_code)]
+
+use proptest::prelude::*;
+use std::fmt;
+
+/*
+ * random programs run on every engine, which all have to give the same
+ * answers. programs get generated as plain numbers and only then turned into
+ * datalog, so proptest can shrink a failure down by shrinking the numbers and
+ * the program that comes out still makes sense:
+ *
+ *   - base relations e0 and e1, derived relations p0, p1 and p2
+ *   - a rule for p2 reads e0, e1, p0, p1 and p2 itself at most once, and only
+ *     negates what it doesn't depend on, so every engine can stratify it
+ *     (and sqlite can recurse through it)
+ *   - head variables, negated atoms and != only use variables the positive
+ *     atoms bind, so every rule is safe. the negated atom and the != can be
+ *     written anywhere in the body, before what binds them too
+ */
+use crate::ast::{BodyExpression, EqualityConstraint, Fact, Rule, Variable};
+use crate::engine::{DatalogEngine, RustEngine};
+use crate::sql::SqliteEngine;
+use crate::topdown::TopDownEngine;
+
+const BASE: usize = 2;
+const DERIVED: usize = 3;
+const CONSTANTS: &[&str] = &["a", "b", "c", "d"];
+const VARS: &[&str] = &["X", "Y", "Z", "W"];
+
+fn relation(i: usize) -> String {
+    if i < BASE {
+        format!("e{}", i)
+    } else {
+        format!("p{}", i - BASE)
+    }
+}
+
+// a term below VARS.len() is that variable, anything above a constant
+fn term(t: usize) -> Variable {
+    if t < VARS.len() {
+        Variable::Free(VARS[t].to_string())
+    } else {
+        Variable::Fixed(CONSTANTS[(t - VARS.len()) % CONSTANTS.len()].to_string())
+    }
+}
+
+#[derive(Clone, Debug)]
+struct RawRule {
+    head: usize,
+    body: Vec<(usize, [usize; 2])>,
+    head_terms: [usize; 2],
+    // each with where in the body it's written
+    negated: Option<((usize, [usize; 2]), usize)>,
+    differ: Option<((usize, usize), usize)>,
+}
+
+fn raw_rule() -> impl Strategy<Value = RawRule> {
+    let atom = || (0..BASE + DERIVED, [0..VARS.len() + 2, 0..VARS.len() + 2]);
+    (
+        0..DERIVED,
+        prop::collection::vec(atom(), 1..4),
+        [0..8usize, 0..8usize],
+        prop::option::weighted(0.3, (atom(), 0..4usize)),
+        prop::option::weighted(0.3, ((0..8usize, 0..8usize), 0..4usize)),
+    )
+        .prop_map(|(head, body, head_terms, negated, differ)| RawRule {
+            head,
+            body,
+            head_terms,
+            negated,
+            differ,
+        })
+}
+
+impl RawRule {
+    fn rule(&self) -> Rule {
+        let head = BASE + self.head;
+        let mut body = vec![];
+        let mut read_itself = false;
+        let mut bound: Vec<Variable> = vec![];
+        for (r, terms) in &self.body {
+            // p_i reads base relations, p_j for j < i and itself
+            let mut r = r % (head + 1);
+            if r == head && read_itself {
+                r = 0;
+            }
+            read_itself |= r == head;
+            let vars: Vec<Variable> = terms.iter().map(|t| term(*t)).collect();
+            for v in &vars {
+                if let Variable::Free(_) = v {
+                    if !bound.contains(v) {
+                        bound.push(v.clone());
+                    }
+                }
+            }
+            body.push(BodyExpression::Fact(Fact {
+                name: relation(r),
+                vars,
+            }));
+        }
+        // a bound variable, or a constant when there aren't any
+        let pick = |i: usize| match bound.len() {
+            0 => term(VARS.len() + i),
+            n => bound[i % n].clone(),
+        };
+        if let Some(((r, terms), at)) = &self.negated {
+            let r = r % head;
+            let not = BodyExpression::Not(Fact {
+                name: relation(r),
+                vars: terms.iter().map(|t| pick(*t)).collect(),
+            });
+            body.insert(at % (body.len() + 1), not);
+        }
+        if let Some(((left, right), at)) = self.differ {
+            let differ = BodyExpression::Equals(EqualityConstraint {
+                equals: false,
+                left: pick(left),
+                right: pick(right),
+            });
+            body.insert(at % (body.len() + 1), differ);
+        }
+        Rule {
+            head: Fact {
+                name: relation(head),
+                vars: self.head_terms.iter().map(|t| pick(*t)).collect(),
+            },
+            body,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Program {
+    facts: Vec<Fact>,
+    rules: Vec<Rule>,
+}
+
+// failures print as datalog, ready to paste into the repl
+impl fmt::Debug for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f)?;
+        for fact in &self.facts {
+            writeln!(f, "{}.", fact)?;
+        }
+        for rule in &self.rules {
+            writeln!(f, "{}", rule)?;
+        }
+        Ok(())
+    }
+}
+
+fn program() -> impl Strategy<Value = Program> {
+    let fact = (0..BASE, 0..CONSTANTS.len(), 0..CONSTANTS.len()).prop_map(|(r, x, y)| Fact {
+        name: relation(r),
+        vars: vec![
+            Variable::Fixed(CONSTANTS[x].to_string()),
+            Variable::Fixed(CONSTANTS[y].to_string()),
+        ],
+    });
+    (prop::collection::vec(fact, 0..12), prop::collection::vec(raw_rule(), 0..6)).prop_map(|(facts, rules)| {
+        Program {
+            facts,
+            rules: rules.iter().map(|r| r.rule()).collect(),
+        }
+    })
+}
+
+fn engines() -> Vec<(&'static str, Box<dyn DatalogEngine>)> {
+    let mut threaded = RustEngine::new();
+    threaded.set_threads(2);
+    let mut written = RustEngine::new();
+    written.pin_written_join_order(true);
+    vec![
+        ("bottom up", Box::new(RustEngine::new())),
+        ("bottom up, 2 threads", Box::new(threaded)),
+        ("bottom up, written join order", Box::new(written)),
+        ("top down", Box::new(TopDownEngine::new())),
+        ("sqlite", Box::new(SqliteEngine::new())),
+    ]
+}
+
+// every query's answers as sorted rows, no answers and an unknown relation alike
+fn answers(engine: &mut dyn DatalogEngine, program: &Program, queries: &[Fact]) -> Vec<Vec<Vec<String>>> {
+    for fact in &program.facts {
+        engine.push_fact(fact.clone()).unwrap();
+    }
+    for rule in &program.rules {
+        engine.push_rule(rule.clone()).unwrap();
+    }
+    queries
+        .iter()
+        .map(|q| {
+            let mut rows: Vec<Vec<String>> = engine
+                .query(q.clone())
+                .unwrap()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|f| f.vars.iter().map(|v| v.to_string()).collect())
+                .collect();
+            rows.sort();
+            rows.dedup();
+            rows
+        })
+        .collect()
+}
+
+// every relation in full, and with its first column bound (which sends
+// recursive queries through magic sets)
+fn queries() -> Vec<Fact> {
+    let mut queries = vec![];
+    for r in 0..BASE + DERIVED {
+        for first in [Variable::Free("X".to_string()), Variable::Fixed("a".to_string())] {
+            queries.push(Fact {
+                name: relation(r),
+                vars: vec![first, Variable::Free("Y".to_string())],
+            });
+        }
+    }
+    queries
+}
+
+proptest! {
+    #[test]
+    fn test_engines_agree(program in program()) {
+        let queries = queries();
+        let mut engines = engines();
+        let (_, first) = &mut engines[0];
+        let expected = answers(first.as_mut(), &program, &queries);
+        for (name, engine) in engines.iter_mut().skip(1) {
+            let got = answers(engine.as_mut(), &program, &queries);
+            for ((q, expected), got) in queries.iter().zip(&expected).zip(&got) {
+                prop_assert_eq!(expected, got, "{}? on {}", q, name);
+            }
+        }
+    }
+}
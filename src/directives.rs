@@ -0,0 +1,60 @@
+#![allow(unused_imports, dead_code)]
+
+use std::path::{Path, PathBuf};
+
+/*
+ * carries out .input and .output, picking the file format from the
+ * directive's parameters. relative paths are taken from `dir`, the directory
+ * of the program file (or the current directory in the REPL)
+ */
+use crate::ast::Directive;
+use crate::delimited::{self, DelimitedOptions};
+use crate::engine::{DatalogEngine, RustEngine};
+use crate::error::Error;
+use crate::json;
+use crate::sqlite::{self, SqliteOptions};
+
+fn is_json(d: &Directive) -> bool {
+    d.param("format") == Some("json") || d.param("format") == Some("jsonl")
+}
+
+/// Loads the facts an `.input` points at, returning how many were new.
+/// Constraints get checked once the whole file is in, not fact by fact, and
+/// if it breaks one none of it stays.
+pub fn input(engine: &mut RustEngine, dir: &Path, d: &Directive) -> Result<usize, Error> {
+    engine.batch(|engine| import(engine, dir, d))?
+}
+
+fn import(engine: &mut RustEngine, dir: &Path, d: &Directive) -> Result<usize, Error> {
+    if d.param("format") == Some("sqlite") {
+        // a table is attached once, and to a relation nothing else makes
+        if engine.is_read_only(&d.relation) {
+            return Err(Error::Schema(format!("{} is already attached", d.relation)));
+        }
+        if engine.written_rules().iter().any(|r| r.head.name == d.relation) {
+            return Err(Error::Schema(format!("{} has rules, it can't be attached", d.relation)));
+        }
+        let opts = SqliteOptions::input(d)?.relative_to(dir);
+        let n = sqlite::import(engine, &d.relation, &opts)?;
+        engine.make_read_only(&d.relation);
+        Ok(n)
+    } else if is_json(d) {
+        json::import(engine, &d.relation, &dir.join(json::path(d)?))
+    } else {
+        delimited::import(engine, &d.relation, &DelimitedOptions::input(d)?.relative_to(dir))
+    }
+}
+
+/// Writes out the relation an `.output` names, returning how many facts
+/// went where.
+pub fn output(engine: &RustEngine, dir: &Path, d: &Directive) -> Result<(usize, PathBuf), Error> {
+    if d.param("format") == Some("sqlite") {
+        Err(Error::Schema("sqlite databases are only read from".to_string()))
+    } else if is_json(d) {
+        let path = dir.join(json::path(d)?);
+        json::export(engine, &d.relation, &path).map(|n| (n, path))
+    } else {
+        let opts = DelimitedOptions::output(d)?.relative_to(dir);
+        delimited::export(engine, &d.relation, &opts).map(|n| (n, opts.path))
+    }
+}
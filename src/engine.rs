@@ -4,123 +4,1391 @@ extern crate time;
 
 use rusqlite::types::ToSql;
 use rusqlite::{params, Connection, Result as SQLResult};
-use std::io::Error;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 use time::Timespec;
 
 /*
  * stores the datalog facts and lets you query them
  */
 use crate::ast::{
-    BodyExpression, EqualityConstraint, Fact, Rule, Statement, Variable, Variable::Fixed,
-    Variable::Free,
+    BodyExpression, ColumnType, Constraint, Count, Declaration, EqualityConstraint, Fact, Rule, Statement,
+    Variable, Variable::Fixed, Variable::Free,
 };
+use crate::error::Error;
+use crate::intern::{Interner, Sym};
+use crate::magic;
+use crate::planner;
+use crate::relation::{hash_row, Log, Relation};
+use crate::safety;
+use crate::strata;
 
-trait DatalogEngine {
-    fn push_fact(&mut self, fact: Fact) -> Result<(), String>;
-    fn push_rule(&mut self, rule: Rule) -> Result<(), String>;
-    fn query(&self, query: Fact) -> Result<Option<Vec<Fact>>, String>;
+pub trait DatalogEngine {
+    /// stores a fact, Ok(false) means it was already known
+    fn push_fact(&mut self, fact: Fact) -> Result<bool, Error>;
+    /// adds a rule, unless it's unsafe or negates its way around a cycle
+    fn push_rule(&mut self, rule: Rule) -> Result<(), Error>;
+    /// takes a stored fact back out, Ok(false) means it wasn't there
+    fn retract_fact(&mut self, fact: Fact) -> Result<bool, Error>;
+    /// adds a constraint, unless it's unsafe or the facts already break it.
+    /// from then on facts and rules that would break it get refused
+    fn push_constraint(&mut self, constraint: Constraint) -> Result<(), Error>;
+    /// starts a transaction: everything pushed from here on can be undone at
+    /// once with `rollback`, and constraints wait until `commit` to be checked
+    fn begin(&mut self) -> Result<(), Error>;
+    /// keeps everything since `begin`, unless it breaks a constraint, in
+    /// which case all of it gets rolled back and the error says what broke
+    fn commit(&mut self) -> Result<(), Error>;
+    /// puts everything back the way it was at `begin`
+    fn rollback(&mut self) -> Result<(), Error>;
+    fn query(&self, query: Fact) -> Result<Option<Vec<Fact>>, Error>;
+    /// gives a relation a schema, facts pushed after this get checked against it
+    fn declare(&mut self, decl: Declaration) -> Result<(), Error>;
+    fn declaration(&self, name: &str) -> Option<&Declaration>;
 }
 
+/// relations are identified by name and arity, `foo(a)` and `foo(a, b)` don't mix
+pub type RelKey = (Sym, usize);
+
+/// A variable or constant inside a rule or query, after interning. Variables
+/// are numbered per rule so bindings can live in a plain vector.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Term {
+    Const(Sym),
+    Var(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Atom {
+    pub relation: RelKey,
+    pub terms: Vec<Term>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Goal {
+    Atom(Atom),
+    Equals { equals: bool, left: Term, right: Term },
+    /// holds when the atom, with every variable bound, isn't in its relation
+    Not(Atom),
+    /// binds `result` to how many rows of the atom's relation match, once the
+    /// variables in `grouped` are bound. the atom's other variables are counted
+    Count { result: Term, atom: Atom, grouped: Vec<usize> },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompiledRule {
+    pub head: Atom,
+    pub body: Vec<Goal>,
+    pub var_count: usize,
+}
+
+pub type Bindings = Vec<Option<Sym>>;
+
+/// where each body atom of a rule reads its tuples from, by position in the body
+type Source<'a> = dyn Fn(usize, RelKey) -> Option<&'a Relation> + Sync + 'a;
+
+/// one rule to run in a round of evaluation, see `RustEngine::fixpoint`
+struct Firing<'r> {
+    rule: &'r CompiledRule,
+    // the body atom reading only last round's new tuples, if any
+    delta_at: Option<usize>,
+    partition: Option<(usize, usize)>,
+}
+
+/// a query's true answers and its undefined ones, see `RustEngine::query_with_undefined`
+pub type Answers = (Vec<Fact>, Vec<Fact>);
+
+/// the head tuples one firing of a rule produced
+type Derived = (RelKey, Vec<Vec<Sym>>);
+
+/// relations smaller than this aren't worth splitting up between threads
+const PARTITION_THRESHOLD: usize = 1024;
+
+/// what a transaction or batch started from, see `DatalogEngine::begin`.
+/// the stored facts aren't copied, the history says how to put them back
+#[derive(Debug)]
+struct Saved {
+    rules: Vec<CompiledRule>,
+    program: Vec<Rule>,
+    constraints: Vec<(Constraint, CompiledRule)>,
+    declarations: HashMap<String, Declaration>,
+    read_only: HashSet<String>,
+    version: u64,
+}
+
+/// how many of the matches a broken constraint lists before giving a count
+const VIOLATIONS_SHOWN: usize = 10;
+
 /// RustEngine is a datalog engine that implements its internals via loops and stuff in Rust
+///
+/// Constants and relation names are interned when they come in, tuples are
+/// stored as packed rows of symbols and only turned back into strings when a
+/// query hands its answers back.
+///
+/// Rule bodies are joined in the order the planner picks, see `planner::plan`.
+#[derive(Debug, Default)]
 pub struct RustEngine {
-    facts: Vec<Fact>,
-    rules: Vec<Rule>,
+    symbols: Interner,
+    relations: HashMap<RelKey, Relation>,
+    rules: Vec<CompiledRule>,
+    // the rules as they were pushed, variable names and all
+    program: Vec<Rule>,
+    // the version each of those was pushed at, see `as_of`
+    pushed: Vec<u64>,
+    written_join_order: bool,
+    threads: usize,
+    // rules that negate their way around a cycle are let in, see `well_founded`
+    well_founded: bool,
+    // each compiled into a rule whose head has a column for each named
+    // variable, so its tuples are the matches. see `check_constraints`
+    constraints: Vec<(Constraint, CompiledRule)>,
+    declarations: HashMap<String, Declaration>,
+    // relations attached from outside, like a sqlite table, that facts can't be added to
+    read_only: HashSet<String>,
+    // bumped by every fact stored or retracted, or once for all of them by
+    // a transaction. `history` is every one of those changes, by relation
+    version: u64,
+    history: HashMap<RelKey, Log>,
+    // while a transaction is open, everything as it was when it started.
+    // rolling back puts that back, derived relations are worked out from it
+    // again the next time they're asked for
+    transaction: Option<Box<Saved>>,
+    // the same while `batch` runs
+    batch: Option<Box<Saved>>,
 }
 
 impl RustEngine {
-    /// For when the query asks for an exact record
-    fn filter_exact_match(&self, query: Fact) -> Vec<Fact> {
-        self.facts
+    pub fn new() -> RustEngine {
+        RustEngine::default()
+    }
+
+    pub fn rules(&self) -> &[CompiledRule] {
+        &self.rules
+    }
+
+    /// the rules as they were pushed, in order
+    pub fn written_rules(&self) -> &[Rule] {
+        &self.program
+    }
+
+    /// the constraints as they were pushed, in order
+    pub fn constraints(&self) -> Vec<&Constraint> {
+        self.constraints.iter().map(|(c, _)| c).collect()
+    }
+
+    /// every declared relation, by name
+    pub fn declarations(&self) -> Vec<&Declaration> {
+        let mut decls: Vec<&Declaration> = self.declarations.values().collect();
+        decls.sort_by(|a, b| a.name.cmp(&b.name));
+        decls
+    }
+
+    /// every stored fact, sorted by relation and then by value so the same
+    /// facts always come out the same way
+    pub fn facts(&self) -> Vec<Fact> {
+        let mut keys: Vec<&RelKey> = self.relations.keys().collect();
+        keys.sort_by_key(|(name, arity)| (self.symbols.resolve(*name), *arity));
+        let mut facts = vec![];
+        for key in keys {
+            let mut rows: Vec<Fact> = self.relations[key]
+                .iter()
+                .map(|row| self.to_fact(*key, row))
+                .collect();
+            rows.sort_by(|a, b| a.vars.iter().map(value).cmp(b.vars.iter().map(value)));
+            facts.extend(rows);
+        }
+        facts
+    }
+
+    /// whether a fact, a rule or a `.decl` has made a relation called `name`,
+    /// of any arity. how names used inside a `.module` get resolved against
+    /// what's already loaded, see `modules::flatten`
+    pub fn defines(&self, name: &str) -> bool {
+        self.declarations.contains_key(name)
+            || self.program.iter().any(|r| r.head.name == name)
+            || self.relations.keys().any(|(n, _)| self.symbols.resolve(*n) == name)
+    }
+
+    /// the facts stored under a relation, not counting anything its rules derive
+    pub fn stored(&self, relation: RelKey) -> Option<&Relation> {
+        self.relations.get(&relation)
+    }
+
+    /// Turns the join planner off (or back on). With it off rule bodies are
+    /// joined left to right exactly as written, which is handy when debugging
+    /// a slow or surprising rule.
+    pub fn pin_written_join_order(&mut self, pinned: bool) {
+        self.written_join_order = pinned;
+    }
+
+    /// How many threads to evaluate rules on, 0 or 1 evaluates everything on
+    /// the calling thread. Answers are the same either way.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads;
+    }
+
+    /// Lets in rules like `win(X) :- move(X, Y), !win(Y).` that negate their
+    /// way back to themselves, evaluated under the well-founded semantics:
+    /// some answers come out true, the rest false, and some undefined, see
+    /// `query_with_undefined`. Turn it on before pushing such rules. Programs
+    /// that stratify give the same answers either way.
+    pub fn set_well_founded(&mut self, on: bool) {
+        self.well_founded = on;
+    }
+
+    /// Refuses any more facts for the relations called `name`, for base
+    /// relations that mirror something outside like a database table.
+    pub fn make_read_only(&mut self, name: &str) {
+        self.read_only.insert(name.to_string());
+    }
+
+    pub fn is_read_only(&self, name: &str) -> bool {
+        self.read_only.contains(name)
+    }
+
+    /// How many times the stored facts have changed, each fact stored or
+    /// retracted is one more. Everything a transaction changes shares the
+    /// version it commits as. 0 is the empty database.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    // logs a change under the version it makes: the next one, or the one the
+    // open transaction will commit as
+    fn record(&mut self, relation: RelKey, row: &[Sym], inserted: bool) {
+        self.version = match &self.transaction {
+            Some(saved) => saved.version + 1,
+            None => self.version + 1,
+        };
+        self.history
+            .entry(relation)
+            .or_insert_with(|| Log::new(relation.1))
+            .push(self.version, row, inserted);
+    }
+
+    /// The database as it was at `version`: the facts stored back then and
+    /// the rules pushed by then, with the constraints and declarations there
+    /// are now. The stored facts get rewound by undoing every later change,
+    /// so this costs a copy of them.
+    ///
+    /// The history only goes back as far as this engine does. `:save` writes
+    /// out the latest version alone, so an engine `:load`ed from it starts
+    /// again from there with nothing earlier to go back to.
+    pub fn as_of(&self, version: u64) -> Result<RustEngine, Error> {
+        if version > self.version {
+            return Err(Error::Evaluation(format!(
+                "there's no version {} yet, the latest is {}",
+                version, self.version
+            )));
+        }
+        let mut relations = self.relations.clone();
+        unwind(&mut relations, &self.history, version);
+        let mut history = self.history.clone();
+        for log in history.values_mut() {
+            log.truncate(version);
+        }
+        history.retain(|_, log| !log.is_empty());
+        let rules = self.pushed.partition_point(|v| *v <= version);
+        Ok(RustEngine {
+            symbols: self.symbols.clone(),
+            relations,
+            rules: self.rules[..rules].to_vec(),
+            program: self.program[..rules].to_vec(),
+            pushed: self.pushed[..rules].to_vec(),
+            written_join_order: self.written_join_order,
+            threads: self.threads,
+            well_founded: self.well_founded,
+            constraints: self.constraints.clone(),
+            declarations: self.declarations.clone(),
+            read_only: self.read_only.clone(),
+            version,
+            history,
+            transaction: None,
+            batch: None,
+        })
+    }
+
+    /// What changed in the answers to `query` from version `from` to version
+    /// `to`: the answers that turned up, then the ones that went away.
+    pub fn diff(&self, query: Fact, from: u64, to: u64) -> Result<(Vec<Fact>, Vec<Fact>), Error> {
+        let answers = |version| -> Result<Vec<Fact>, Error> {
+            Ok(self.as_of(version)?.query(query.clone())?.unwrap_or_default())
+        };
+        let (before, after) = (answers(from)?, answers(to)?);
+        let added = after.iter().filter(|a| !before.contains(a)).cloned().collect();
+        let removed = before.iter().filter(|b| !after.contains(b)).cloned().collect();
+        Ok((added, removed))
+    }
+
+    /// whether `begin` has been called without a `commit` or `rollback` since
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+
+    /// Runs `f` with constraints checked once when it's done instead of
+    /// after every fact and rule, for loading a whole program or file at a
+    /// time. If one is broken by then, everything `f` changed is undone and
+    /// the error says what broke. Inside a transaction or another batch this
+    /// is just `f`, whatever's outside does the checking.
+    pub fn batch<T>(&mut self, f: impl FnOnce(&mut RustEngine) -> T) -> Result<T, Error> {
+        if self.checks_deferred() {
+            return Ok(f(self));
+        }
+        self.batch = Some(Box::new(self.save()));
+        let result = f(self);
+        let saved = self.batch.take().unwrap();
+        if let Err(e) = self.check_changes(&saved) {
+            self.restore(*saved);
+            return Err(e);
+        }
+        Ok(result)
+    }
+
+    // whether constraints are waiting for a commit or the end of a batch
+    fn checks_deferred(&self) -> bool {
+        self.transaction.is_some() || self.batch.is_some()
+    }
+
+    fn save(&self) -> Saved {
+        Saved {
+            rules: self.rules.clone(),
+            program: self.program.clone(),
+            constraints: self.constraints.clone(),
+            declarations: self.declarations.clone(),
+            read_only: self.read_only.clone(),
+            version: self.version,
+        }
+    }
+
+    // puts everything back the way it was when `saved` was, undoing the
+    // facts stored and retracted since
+    fn restore(&mut self, saved: Saved) {
+        unwind(&mut self.relations, &self.history, saved.version);
+        self.relations.retain(|_, r| !r.is_empty());
+        for log in self.history.values_mut() {
+            log.truncate(saved.version);
+        }
+        self.history.retain(|_, log| !log.is_empty());
+        self.pushed.truncate(saved.rules.len());
+        self.rules = saved.rules;
+        self.program = saved.program;
+        self.constraints = saved.constraints;
+        self.declarations = saved.declarations;
+        self.read_only = saved.read_only;
+        self.version = saved.version;
+    }
+
+    // checks the constraints that what's happened since `saved` could have
+    // broken: all of them if any are new, or else the ones reading from the
+    // relations that changed
+    fn check_changes(&self, saved: &Saved) -> Result<(), Error> {
+        if self.constraints.len() != saved.constraints.len() {
+            return self.check_constraints(None);
+        }
+        let mut changed: Vec<RelKey> = self
+            .history
             .iter()
-            .filter(|e| query == **e)
-            .map(|e| e.clone())
+            .filter(|(_, log)| log.until(saved.version) < log.len())
+            .map(|(key, _)| *key)
+            .collect();
+        changed.extend(self.rules[saved.rules.len()..].iter().map(|r| r.head.relation));
+        changed.sort();
+        changed.dedup();
+        if changed.is_empty() {
+            return Ok(());
+        }
+        self.check_constraints(Some(&changed))
+    }
+
+    /// bytes held on the heap by the symbol table, the stored tuples and
+    /// their history
+    pub fn heap_bytes(&self) -> usize {
+        self.symbols.heap_bytes()
+            + self
+                .relations
+                .values()
+                .map(|r| r.heap_bytes() + mem::size_of::<Relation>())
+                .sum::<usize>()
+            + self
+                .history
+                .values()
+                .map(|log| log.heap_bytes() + mem::size_of::<Log>())
+                .sum::<usize>()
+    }
+
+    fn intern_term(&mut self, v: &Variable, vars: &mut Vec<String>) -> Term {
+        match v {
+            Fixed(s) => Term::Const(self.symbols.intern(s)),
+            Free(s) => Term::Var(slot(vars, s)),
+        }
+    }
+
+    fn intern_atom(&mut self, f: &Fact, vars: &mut Vec<String>) -> Atom {
+        let name = self.symbols.intern(&f.name);
+        Atom {
+            relation: (name, f.vars.len()),
+            terms: f.vars.iter().map(|v| self.intern_term(v, vars)).collect(),
+        }
+    }
+
+    // `counted` are the variables only a count uses, see `safety::counted`
+    fn compile_body(&mut self, body: &[BodyExpression], vars: &mut Vec<String>, counted: &HashSet<&str>) -> Vec<Goal> {
+        body.iter()
+            .map(|b| match b {
+                BodyExpression::Fact(f) => Goal::Atom(self.intern_atom(f, vars)),
+                BodyExpression::Not(f) => Goal::Not(self.intern_atom(f, vars)),
+                BodyExpression::Equals(e) => Goal::Equals {
+                    equals: e.equals,
+                    left: self.intern_term(&e.left, vars),
+                    right: self.intern_term(&e.right, vars),
+                },
+                BodyExpression::Count(c) => {
+                    let result = self.intern_term(&c.result, vars);
+                    let atom = self.intern_atom(&c.over, vars);
+                    let mut grouped = vec![];
+                    for t in &atom.terms {
+                        if let Term::Var(i) = t {
+                            if vars[*i] != "_" && !counted.contains(vars[*i].as_str()) && !grouped.contains(i) {
+                                grouped.push(*i);
+                            }
+                        }
+                    }
+                    Goal::Count { result, atom, grouped }
+                }
+            })
             .collect()
     }
 
-    fn get_relation(&self, name: &str, column_count: usize) -> Vec<&Fact> {
-        self.facts
+    fn compile_rule(&mut self, rule: &Rule) -> CompiledRule {
+        let mut vars = vec![];
+        let head = self.intern_atom(&rule.head, &mut vars);
+        let counted = safety::counted(&rule.head.vars, &rule.body);
+        let body = self.compile_body(&rule.body, &mut vars, &counted);
+        CompiledRule {
+            head,
+            body,
+            var_count: vars.len(),
+        }
+    }
+
+    // the head is `:-`, which no relation can be called, with every named
+    // variable as a column. wildcards and what a count counts are never
+    // bound so they can't be in it
+    fn compile_constraint(&mut self, constraint: &Constraint) -> CompiledRule {
+        let mut vars = vec![];
+        let counted = safety::counted(&[], &constraint.body);
+        let body = self.compile_body(&constraint.body, &mut vars, &counted);
+        let terms: Vec<Term> = (0..vars.len())
+            .filter(|i| vars[*i] != "_" && !counted.contains(vars[*i].as_str()))
+            .map(Term::Var)
+            .collect();
+        CompiledRule {
+            head: Atom {
+                relation: (self.symbols.intern(":-"), terms.len()),
+                terms,
+            },
+            body,
+            var_count: vars.len(),
+        }
+    }
+
+    /// Like `intern_atom` but for queries, which only borrow the engine. None
+    /// means the query mentions a name or constant that isn't stored anywhere.
+    pub fn lookup_atom(&self, f: &Fact, vars: &mut Vec<String>) -> Option<Atom> {
+        let name = self.symbols.get(&f.name)?;
+        let mut terms = vec![];
+        for v in &f.vars {
+            terms.push(match v {
+                Fixed(s) => Term::Const(self.symbols.get(s)?),
+                Free(s) => Term::Var(slot(vars, s)),
+            });
+        }
+        Some(Atom {
+            relation: (name, f.vars.len()),
+            terms,
+        })
+    }
+
+    /// Answers a query like `DatalogEngine::query`, along with the answers
+    /// that are undefined under the well-founded semantics (see
+    /// `set_well_founded`). Those are never among the true ones, and there
+    /// aren't any unless the relation depends on a rule that negates its way
+    /// around a cycle.
+    pub fn query_with_undefined(&self, query: Fact) -> Result<Option<Answers>, Error> {
+        let mut vars = vec![];
+        let atom = match self.lookup_atom(&query, &mut vars) {
+            Some(a) => a,
+            None => {
+                // nothing by that name, or a constant that was never stored
+                return if self.symbols.get(&query.name).is_some() {
+                    Ok(Some((vec![], vec![])))
+                } else {
+                    Ok(None)
+                };
+            }
+        };
+        if !self.relations.contains_key(&atom.relation) && !self.is_derived(atom.relation) {
+            return Ok(None);
+        }
+        let answers = |r: &Relation| -> Vec<Fact> {
+            r.iter()
+                .filter(|row| matches(&atom.terms, row))
+                .map(|row| self.to_fact(atom.relation, row))
+                .collect()
+        };
+        if self.well_founded && self.is_derived(atom.relation) && self.unstratified(atom.relation) {
+            let (truth, possible) = self.well_founded_model(atom.relation);
+            let truth = &truth[&atom.relation];
+            let undefined = possible[&atom.relation]
+                .iter()
+                .filter(|row| matches(&atom.terms, row) && !truth.contains(row))
+                .map(|row| self.to_fact(atom.relation, row))
+                .collect();
+            return Ok(Some((answers(truth), undefined)));
+        }
+        let has_constants = atom.terms.iter().any(|t| match t {
+            Term::Const(_) => true,
+            Term::Var(_) => false,
+        });
+        let answers = if has_constants && self.is_recursive(atom.relation) && !self.negates_or_counts(atom.relation) {
+            // don't work out all of `path` just to answer `path(a, X)?`. the
+            // rewrite doesn't carry negated relations along, those get the whole thing
+            answers(&self.evaluate_goal_directed(&atom))
+        } else if self.is_derived(atom.relation) {
+            let derived = self.evaluate(atom.relation);
+            answers(&derived[&atom.relation])
+        } else {
+            // stored tuples get filtered in place, no need to copy the whole relation out
+            answers(&self.relations[&atom.relation])
+        };
+        Ok(Some((answers, vec![])))
+    }
+
+    pub fn to_fact(&self, relation: RelKey, row: &[Sym]) -> Fact {
+        Fact {
+            name: self.symbols.resolve(relation.0).to_string(),
+            vars: row
+                .iter()
+                .map(|s| Fixed(self.symbols.resolve(*s).to_string()))
+                .collect(),
+        }
+    }
+
+    pub fn is_derived(&self, relation: RelKey) -> bool {
+        self.rules.iter().any(|r| r.head.relation == relation)
+    }
+
+    /// every relation `relation` is computed from, itself included
+    fn dependencies(&self, relation: RelKey) -> HashSet<RelKey> {
+        let mut seen = HashSet::new();
+        let mut todo = vec![relation];
+        while let Some(r) = todo.pop() {
+            if !seen.insert(r) {
+                continue;
+            }
+            for rule in self.rules.iter().filter(|rule| rule.head.relation == r) {
+                for goal in &rule.body {
+                    if let Goal::Atom(a) | Goal::Not(a) | Goal::Count { atom: a, .. } = goal {
+                        todo.push(a.relation);
+                    }
+                }
+            }
+        }
+        seen
+    }
+
+    /// whether any rule `relation` is computed from negates or counts
+    /// something, which magic sets can't rewrite
+    fn negates_or_counts(&self, relation: RelKey) -> bool {
+        let needed = self.dependencies(relation);
+        self.rules
             .iter()
-            .filter(|r| r.name == name && r.vars.len() == column_count)
-            .collect()
+            .filter(|r| needed.contains(&r.head.relation))
+            .flat_map(|r| r.body.iter())
+            .any(|goal| match goal {
+                Goal::Not(_) | Goal::Count { .. } => true,
+                Goal::Atom(_) | Goal::Equals { .. } => false,
+            })
+    }
+
+    /// Computes every derived relation that `relation` depends on, bottom up,
+    /// until the rules stop producing tuples that weren't known already.
+    ///
+    /// This is semi-naive evaluation: after the first round a rule only fires
+    /// with at least one of its body atoms reading the tuples that were new in
+    /// the round before, so the same tuples aren't rederived from the same
+    /// inputs over and over.
+    fn evaluate(&self, relation: RelKey) -> HashMap<RelKey, Relation> {
+        let needed = self.dependencies(relation);
+        let rules: Vec<&CompiledRule> = self
+            .rules
+            .iter()
+            .filter(|r| needed.contains(&r.head.relation))
+            .collect();
+        self.fixpoint(&rules, HashMap::new(), None)
+    }
+
+    /// Whether some rule `relation` depends on negates its way around a cycle,
+    /// which only gets past `push_rule` with `set_well_founded`.
+    fn unstratified(&self, relation: RelKey) -> bool {
+        let needed = self.dependencies(relation);
+        let rules: Vec<&CompiledRule> = self
+            .rules
+            .iter()
+            .filter(|r| needed.contains(&r.head.relation))
+            .collect();
+        strata::negative_cycle(&rules).is_some()
+    }
+
+    /// The well-founded model of what `relation` depends on, by alternating
+    /// fixpoints: with `!p(x)` read against a fixed guess of `p`, the rules
+    /// are plain positive ones. Reading negations against too little
+    /// overestimates what's true, against too much underestimates it, and
+    /// the two estimates feed each other until the underestimate stops
+    /// growing.
+    ///
+    /// Returns what's true and what's true or undefined, everything else is
+    /// false.
+    fn well_founded_model(&self, relation: RelKey) -> (HashMap<RelKey, Relation>, HashMap<RelKey, Relation>) {
+        let needed = self.dependencies(relation);
+        let rules: Vec<&CompiledRule> = self
+            .rules
+            .iter()
+            .filter(|r| needed.contains(&r.head.relation))
+            .collect();
+        let size = |relations: &HashMap<RelKey, Relation>| relations.values().map(|r| r.len()).sum::<usize>();
+        let mut truth = HashMap::new();
+        loop {
+            let possible = self.fixpoint(&rules, HashMap::new(), Some(&truth));
+            let next = self.fixpoint(&rules, HashMap::new(), Some(&possible));
+            // the underestimate only ever grows, the same size means the same tuples
+            if size(&next) == size(&truth) {
+                return (next, possible);
+            }
+            truth = next;
+        }
     }
 
-    // pulls records of a relation, leaving filtering on fixed vars
-    fn select(&self, query: Fact) -> Vec<Fact> {
-        let mut results = vec![];
-        for record in self.get_relation(&query.name, query.vars.len()) {
-            // TODO: forget about free vars matching lol. just pretend each free var is a wildcard
-            // pairwise comparison of each side
-            let mut record_matches = true;
-            for (q, r) in query.vars.iter().zip(&record.vars) {
-                match q {
-                    v @ Fixed(_) => {
-                        if v != r {
-                            record_matches = false;
+    /// Refuses the database as it stands if any constraint's body matches
+    /// something, saying which constraint and what it matched. With
+    /// `changed` only the constraints that read from one of those relations,
+    /// directly or through rules, get checked.
+    fn check_constraints(&self, changed: Option<&[RelKey]>) -> Result<(), Error> {
+        for (constraint, compiled) in &self.constraints {
+            let mut needed = HashSet::new();
+            for goal in &compiled.body {
+                if let Goal::Atom(a) | Goal::Not(a) | Goal::Count { atom: a, .. } = goal {
+                    needed.extend(self.dependencies(a.relation));
+                }
+            }
+            if changed.is_some_and(|changed| !changed.iter().any(|r| needed.contains(r))) {
+                continue;
+            }
+            let mut rules: Vec<&CompiledRule> = self
+                .rules
+                .iter()
+                .filter(|r| needed.contains(&r.head.relation))
+                .collect();
+            rules.push(compiled);
+            let found = &self.fixpoint(&rules, HashMap::new(), None)[&compiled.head.relation];
+            if !found.is_empty() {
+                return Err(self.violated(constraint, found));
+            }
+        }
+        Ok(())
+    }
+
+    // the constraint's body with each match's values put in for its variables
+    fn violated(&self, constraint: &Constraint, found: &Relation) -> Error {
+        // in the order `compile_constraint` made them columns
+        let counted = safety::counted(&[], &constraint.body);
+        let mut names = vec![];
+        for e in &constraint.body {
+            for v in e.variables() {
+                if let Free(name) = v {
+                    if !v.is_wildcard() && !counted.contains(name.as_str()) && !names.contains(name) {
+                        names.push(name.clone());
+                    }
+                }
+            }
+        }
+        let mut matches: Vec<String> = found
+            .iter()
+            .map(|row| {
+                let value = |v: &Variable| match v {
+                    Free(name) => match names.iter().position(|n| n == name) {
+                        Some(i) => Fixed(self.symbols.resolve(row[i]).to_string()),
+                        None => v.clone(),
+                    },
+                    v => v.clone(),
+                };
+                let fact = |f: &Fact| Fact {
+                    name: f.name.clone(),
+                    vars: f.vars.iter().map(value).collect(),
+                };
+                let body: Vec<String> = constraint
+                    .body
+                    .iter()
+                    .map(|e| match e {
+                        BodyExpression::Fact(f) => BodyExpression::Fact(fact(f)),
+                        BodyExpression::Not(f) => BodyExpression::Not(fact(f)),
+                        BodyExpression::Equals(e) => BodyExpression::Equals(EqualityConstraint {
+                            equals: e.equals,
+                            left: value(&e.left),
+                            right: value(&e.right),
+                        }),
+                        BodyExpression::Count(c) => BodyExpression::Count(Count {
+                            result: value(&c.result),
+                            over: fact(&c.over),
+                        }),
+                    })
+                    .map(|e| e.to_string())
+                    .collect();
+                body.join(", ")
+            })
+            .collect();
+        matches.sort();
+        let mut shown = matches.iter().take(VIOLATIONS_SHOWN).cloned().collect::<Vec<_>>().join("; ");
+        if matches.len() > VIOLATIONS_SHOWN {
+            shown.push_str(&format!(" and {} more", matches.len() - VIOLATIONS_SHOWN));
+        }
+        // a line number is no help without the file it's in
+        let place = match &constraint.file {
+            Some(file) => format!(" at {}:{}", file, constraint.line),
+            None => String::new(),
+        };
+        Error::Constraint(format!("{}{} by {}", constraint, place, shown))
+    }
+
+    /// Whether a derived relation is defined in terms of itself, directly or
+    /// through other rules.
+    fn is_recursive(&self, relation: RelKey) -> bool {
+        self.rules
+            .iter()
+            .filter(|r| r.head.relation == relation)
+            .flat_map(|r| r.body.iter())
+            .any(|goal| match goal {
+                Goal::Atom(a) | Goal::Not(a) | Goal::Count { atom: a, .. } => {
+                    self.dependencies(a.relation).contains(&relation)
+                }
+                Goal::Equals { .. } => false,
+            })
+    }
+
+    /// Answers a query through the magic sets rewrite of the rules, see
+    /// `magic::rewrite`. Returns the part of the queried relation that was
+    /// relevant to the query's constants, which can be far less than all of it.
+    fn evaluate_goal_directed(&self, query: &Atom) -> Relation {
+        let program = magic::rewrite(&self.rules, query, self.symbols.len());
+        let (seed_relation, seed) = program.seed;
+        let mut seeds = HashMap::new();
+        let mut magic = Relation::new(seed_relation.1);
+        magic.push(&seed);
+        seeds.insert(seed_relation, magic);
+
+        let rules: Vec<&CompiledRule> = program.rules.iter().collect();
+        let mut derived = self.fixpoint(&rules, seeds, None);
+        derived
+            .remove(&program.answer)
+            .unwrap_or_else(|| Relation::new(query.relation.1))
+    }
+
+    /// Runs `rules` to a fixpoint, starting from the tuples in `full` on top
+    /// of the stored ones. Returns every relation the rules derive into.
+    ///
+    /// Rules are run one stratum at a time (see `strata::strata`), each one
+    /// semi-naively. Within a round every rule firing only reads what earlier
+    /// rounds produced, so they can all run at once on `self.threads` threads.
+    ///
+    /// With `assumed` negated atoms read from there (or the stored facts)
+    /// instead of what's been derived, see `well_founded_model`.
+    fn fixpoint(
+        &self,
+        rules: &[&CompiledRule],
+        mut full: HashMap<RelKey, Relation>,
+        assumed: Option<&HashMap<RelKey, Relation>>,
+    ) -> HashMap<RelKey, Relation> {
+        // derived relations start out with whatever facts were stored under the same name
+        for rule in rules {
+            let key = rule.head.relation;
+            full.entry(key).or_insert_with(|| match self.relations.get(&key) {
+                Some(stored) => stored.clone(),
+                None => Relation::new(key.1),
+            });
+        }
+
+        for stratum in strata::strata(rules) {
+            let rules: Vec<&CompiledRule> = stratum.iter().map(|i| rules[*i]).collect();
+            let firings: Vec<Firing> = rules
+                .iter()
+                .flat_map(|rule| self.partitions(rule, &|_, key| self.current(&full, key)))
+                .map(|(rule, partition)| Firing {
+                    rule,
+                    delta_at: None,
+                    partition,
+                })
+                .collect();
+            let derived = self.fire(&firings, &full, &HashMap::new(), assumed);
+            let mut delta = absorb(&mut full, derived);
+
+            while !delta.is_empty() {
+                let mut firings = vec![];
+                for rule in &rules {
+                    for (i, goal) in rule.body.iter().enumerate() {
+                        match goal {
+                            Goal::Atom(a) if delta.contains_key(&a.relation) => {}
+                            _ => continue,
+                        }
+                        let source = |j: usize, key: RelKey| {
+                            if i == j {
+                                delta.get(&key)
+                            } else {
+                                self.current(&full, key)
+                            }
+                        };
+                        for (rule, partition) in self.partitions(rule, &source) {
+                            firings.push(Firing {
+                                rule,
+                                delta_at: Some(i),
+                                partition,
+                            });
                         }
                     }
-                    _v @ Free(_) => {
-                        // TODO: somehow trace the freevars.  this has to work both within the same relation but also across relations (joins)
-                        continue;
+                }
+                let derived = self.fire(&firings, &full, &delta, assumed);
+                delta = absorb(&mut full, derived);
+            }
+        }
+        full
+    }
+
+    /// Splits one firing of a rule into `self.threads` pieces when the atom
+    /// its join starts from is big enough to be worth it, so a single heavy
+    /// rule doesn't keep all but one thread idle.
+    fn partitions<'r>(
+        &self,
+        rule: &'r CompiledRule,
+        source: &Source,
+    ) -> Vec<(&'r CompiledRule, Option<(usize, usize)>)> {
+        let first = self.join_order(rule, source).into_iter().find_map(|i| match &rule.body[i] {
+            Goal::Atom(a) => Some(source(i, a.relation).map_or(0, |r| r.len())),
+            Goal::Equals { .. } | Goal::Not(_) | Goal::Count { .. } => None,
+        });
+        match first {
+            Some(size) if self.threads > 1 && size >= PARTITION_THRESHOLD => (0..self.threads)
+                .map(|part| (rule, Some((part, self.threads))))
+                .collect(),
+            _ => vec![(rule, None)],
+        }
+    }
+
+    // runs a round's worth of firings, in parallel if there are threads to do it with
+    fn fire(
+        &self,
+        firings: &[Firing],
+        full: &HashMap<RelKey, Relation>,
+        delta: &HashMap<RelKey, Relation>,
+        assumed: Option<&HashMap<RelKey, Relation>>,
+    ) -> Vec<Derived> {
+        let run = |f: &Firing| {
+            let source = |j: usize, key: RelKey| match (&f.rule.body[j], assumed) {
+                (Goal::Not(_), Some(assumed)) => assumed.get(&key).or_else(|| self.relations.get(&key)),
+                _ if f.delta_at == Some(j) => delta.get(&key),
+                _ => self.current(full, key),
+            };
+            (
+                f.rule.head.relation,
+                self.select_from_rule(f.rule, &source, f.partition),
+            )
+        };
+        if self.threads <= 1 || firings.len() <= 1 {
+            return firings.iter().map(run).collect();
+        }
+
+        let next = AtomicUsize::new(0);
+        let mut results: Vec<(usize, Derived)> = thread::scope(|s| {
+            let workers: Vec<_> = (0..self.threads.min(firings.len()))
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut done = vec![];
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            if i >= firings.len() {
+                                return done;
+                            }
+                            done.push((i, run(&firings[i])));
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|w| w.join().expect("evaluation thread panicked"))
+                .collect()
+        });
+        // absorb in the same order the sequential evaluator would
+        results.sort_by_key(|(i, _)| *i);
+        results.into_iter().map(|(_, r)| r).collect()
+    }
+
+    // a derived relation as computed so far, or a stored one
+    fn current<'a>(
+        &'a self,
+        derived: &'a HashMap<RelKey, Relation>,
+        relation: RelKey,
+    ) -> Option<&'a Relation> {
+        derived
+            .get(&relation)
+            .or_else(|| self.relations.get(&relation))
+    }
+
+    fn join_order(&self, rule: &CompiledRule, source: &Source) -> Vec<usize> {
+        if self.written_join_order {
+            planner::written(&rule.body, rule.var_count)
+        } else {
+            planner::plan(&rule.body, rule.var_count, &|position, relation| {
+                source(position, relation).map_or(0, |r| r.len())
+            })
+        }
+    }
+
+    /// Joins the rule body one goal at a time and returns the head tuples it
+    /// produces. `source` says where the body atom at a given position reads
+    /// its tuples from. With a `partition` of `(part, parts)` only the rows of
+    /// the first atom joined whose hash lands in `part` are used.
+    fn select_from_rule(
+        &self,
+        rule: &CompiledRule,
+        source: &Source,
+        partition: Option<(usize, usize)>,
+    ) -> Vec<Vec<Sym>> {
+        let order = self.join_order(rule, source);
+        let mut partition = partition;
+
+        let mut solutions: Vec<Bindings> = vec![vec![None; rule.var_count]];
+        // which variables the goals so far have bound, the same for every solution
+        let mut bound = vec![false; rule.var_count];
+        for position in order {
+            let goal = &rule.body[position];
+            let mut next = vec![];
+            match goal {
+                Goal::Atom(atom) => {
+                    let rows = match source(position, atom.relation) {
+                        Some(rows) => rows,
+                        None => return vec![],
+                    };
+                    // hash join on whatever columns are already known
+                    let key_columns: Vec<usize> = (0..atom.terms.len())
+                        .filter(|c| planner::is_bound(atom.terms[*c], &bound))
+                        .collect();
+                    let mut index: HashMap<Vec<Sym>, Vec<&[Sym]>> = HashMap::new();
+                    let part = partition.take();
+                    for row in rows.iter() {
+                        if let Some((part, parts)) = part {
+                            if hash_row(row) as usize % parts != part {
+                                continue;
+                            }
+                        }
+                        let key = key_columns.iter().map(|c| row[*c]).collect();
+                        index.entry(key).or_default().push(row);
+                    }
+                    for b in &solutions {
+                        let key: Option<Vec<Sym>> = key_columns
+                            .iter()
+                            .map(|c| resolve(atom.terms[*c], b))
+                            .collect();
+                        let candidates = match key.and_then(|k| index.get(&k)) {
+                            Some(candidates) => candidates,
+                            None => continue,
+                        };
+                        for row in candidates {
+                            if let Some(extended) = unify(&atom.terms, row, b) {
+                                next.push(extended);
+                            }
+                        }
+                    }
+                }
+                Goal::Equals {
+                    equals,
+                    left,
+                    right,
+                } => {
+                    next.extend(
+                        solutions
+                            .into_iter()
+                            .filter_map(|b| compare(*equals, *left, *right, b)),
+                    );
+                }
+                Goal::Not(atom) => {
+                    // the planner only gets here once every variable but `_` is bound
+                    let rows = source(position, atom.relation);
+                    next.extend(solutions.into_iter().filter(|b| {
+                        let row: Option<Vec<Sym>> = atom.terms.iter().map(|t| resolve(*t, b)).collect();
+                        match (row, rows) {
+                            (Some(row), Some(rows)) => !rows.contains(&row),
+                            (_, None) => true,
+                            (None, Some(rows)) => !rows.iter().any(|row| unify(&atom.terms, row, b).is_some()),
+                        }
+                    }));
+                }
+                Goal::Count { result, atom, .. } => {
+                    // the rows by the columns known by now, the rest get counted
+                    let key_columns: Vec<usize> = (0..atom.terms.len())
+                        .filter(|c| planner::is_bound(atom.terms[*c], &bound))
+                        .collect();
+                    let mut index: HashMap<Vec<Sym>, Vec<&[Sym]>> = HashMap::new();
+                    for row in source(position, atom.relation).iter().flat_map(|r| r.iter()) {
+                        index.entry(key_columns.iter().map(|c| row[*c]).collect()).or_default().push(row);
+                    }
+                    for b in &solutions {
+                        let key: Option<Vec<Sym>> = key_columns.iter().map(|c| resolve(atom.terms[*c], b)).collect();
+                        let rows = key.and_then(|k| index.get(&k)).map_or(&[][..], |rows| rows.as_slice());
+                        let n = rows.iter().filter(|row| unify(&atom.terms, row, b).is_some()).count();
+                        next.extend(unify(&[*result], &[count(n)], b));
                     }
                 }
             }
-            if record_matches {
-                results.push(record.clone());
+            planner::bind(goal, &mut bound);
+            solutions = next;
+        }
+        solutions
+            .iter()
+            .filter_map(|b| {
+                rule.head
+                    .terms
+                    .iter()
+                    .map(|t| resolve(*t, b))
+                    .collect::<Option<Vec<_>>>()
+            })
+            .collect()
+    }
+}
+
+/// adds freshly derived rows to the relations they belong to, returning the
+/// ones that weren't there yet
+fn absorb(full: &mut HashMap<RelKey, Relation>, derived: Vec<Derived>) -> HashMap<RelKey, Relation> {
+    let mut delta = HashMap::new();
+    for (key, rows) in derived {
+        let relation = full
+            .get_mut(&key)
+            .expect("derived relations are set up before evaluating");
+        for row in rows {
+            if relation.push(&row) {
+                delta
+                    .entry(key)
+                    .or_insert_with(|| Relation::new(key.1))
+                    .push(&row);
             }
         }
-        results
     }
+    delta
+}
 
-    fn select_from_rule(&self, query: Fact) -> Vec<Fact> {
-        let matching_rules = self.rules.iter().filter(|r| r.head.name == query.name && r.head.vars.len() == query.vars.len()).collect::<Vec<_>>();
-        // TODO: hardcode bodies to be only 1 fact for now
-        // bar(X) :- foo(X).
-        let r = matching_rules[0];
-        let r_fact = match &r.body[0] {
-            BodyExpression::Fact(f) => f,
-            BodyExpression::Equals(_) => panic!("no idea how to deal with eq body rules"),
-        };
-        self.select(r_fact.clone())
+/// puts `relations` back the way they were at `version`, undoing the later
+/// changes in `history`
+fn unwind(relations: &mut HashMap<RelKey, Relation>, history: &HashMap<RelKey, Log>, version: u64) {
+    for (key, log) in history {
+        if log.until(version) < log.len() {
+            log.undo(relations.entry(*key).or_insert_with(|| Relation::new(key.1)), version);
+        }
+    }
+}
+
+fn value(v: &Variable) -> &str {
+    match v {
+        Fixed(s) | Free(s) => s,
+    }
+}
+
+fn slot(vars: &mut Vec<String>, name: &str) -> usize {
+    // every `_` is a variable of its own
+    match vars.iter().position(|v| v == name && name != "_") {
+        Some(i) => i,
+        None => {
+            vars.push(name.to_string());
+            vars.len() - 1
+        }
+    }
+}
+
+pub fn resolve(t: Term, b: &Bindings) -> Option<Sym> {
+    match t {
+        Term::Const(s) => Some(s),
+        Term::Var(i) => b[i],
+    }
+}
+
+/// the symbol for a count of `n`
+pub fn count(n: usize) -> Sym {
+    Sym::number(n).expect("counted more rows than a relation holds")
+}
+
+/// runs `left = right` (or `left != right`) against one set of bindings
+pub fn compare(equals: bool, left: Term, right: Term, b: Bindings) -> Option<Bindings> {
+    match (resolve(left, &b), resolve(right, &b)) {
+        (Some(l), Some(r)) if (l == r) == equals => Some(b),
+        (Some(_), Some(_)) => None,
+        // `X = a` with X not bound yet binds it
+        (None, Some(s)) | (Some(s), None) if equals => {
+            let mut b = b;
+            for t in &[left, right] {
+                if let Term::Var(i) = t {
+                    b[*i] = Some(s);
+                }
+            }
+            Some(b)
+        }
+        // push_rule turns away rules that could get here, see `safety::check`
+        _ => None,
+    }
+}
+
+/// matches a row against the terms of an atom, returning the bindings grown by
+/// whatever variables the row pins down. Repeated variables must agree, so
+/// `edge(X, X)` only matches self loops.
+pub fn unify(terms: &[Term], row: &[Sym], b: &Bindings) -> Option<Bindings> {
+    let mut b = b.clone();
+    for (t, s) in terms.iter().zip(row) {
+        match t {
+            Term::Const(c) => {
+                if c != s {
+                    return None;
+                }
+            }
+            Term::Var(i) => match b[*i] {
+                Some(bound) if bound != *s => return None,
+                Some(_) => {}
+                None => b[*i] = Some(*s),
+            },
+        }
     }
+    Some(b)
+}
+
+/// same answer as `unify(terms, row, &no_bindings).is_some()` without allocating,
+/// for scanning a whole relation
+pub fn matches(terms: &[Term], row: &[Sym]) -> bool {
+    terms.iter().zip(row).enumerate().all(|(i, (t, s))| match t {
+        Term::Const(c) => c == s,
+        // only the first occurrence of a variable is free, later ones must agree with it
+        Term::Var(_) => match terms[..i].iter().position(|earlier| earlier == t) {
+            Some(j) => row[j] == *s,
+            None => true,
+        },
+    })
 }
 
 impl DatalogEngine for RustEngine {
     // TODO: add constraint to make sure a rule and a fact cannot have the same name
-    fn push_fact(&mut self, fact: Fact) -> Result<(), String> {
-        self.facts.push(fact);
+    fn push_fact(&mut self, fact: Fact) -> Result<bool, Error> {
+        if self.read_only.contains(&fact.name) {
+            return Err(Error::Schema(format!("{} is read only", fact.name)));
+        }
+        if let Some(decl) = self.declarations.get(&fact.name) {
+            check_declared(decl, &fact)?;
+        }
+        let name = self.symbols.intern(&fact.name);
+        let mut row = Vec::with_capacity(fact.vars.len());
+        for v in &fact.vars {
+            match v {
+                Fixed(s) => row.push(self.symbols.intern(s)),
+                Free(s) => {
+                    return Err(Error::Safety(format!(
+                        "facts can't have free variables, found {}",
+                        s
+                    )))
+                }
+            }
+        }
+        let key = (name, row.len());
+        let new = self
+            .relations
+            .entry(key)
+            .or_insert_with(|| Relation::new(row.len()))
+            .push(&row);
+        // a transaction only checks constraints once it commits
+        if new && !self.checks_deferred() {
+            if let Err(e) = self.check_constraints(Some(&[key])) {
+                let relation = self.relations.get_mut(&key).unwrap();
+                relation.remove(&row);
+                if relation.is_empty() {
+                    self.relations.remove(&key);
+                }
+                return Err(e);
+            }
+        }
+        if new {
+            self.record(key, &row, true);
+        }
+        Ok(new)
+    }
+
+    fn retract_fact(&mut self, fact: Fact) -> Result<bool, Error> {
+        if self.read_only.contains(&fact.name) {
+            return Err(Error::Schema(format!("{} is read only", fact.name)));
+        }
+        let mut row = Vec::with_capacity(fact.vars.len());
+        for v in &fact.vars {
+            match v {
+                // never stored, so there's nothing to take out
+                Fixed(s) => match self.symbols.get(s) {
+                    Some(sym) => row.push(sym),
+                    None => return Ok(false),
+                },
+                Free(s) => {
+                    return Err(Error::Safety(format!(
+                        "facts can't have free variables, found {}",
+                        s
+                    )))
+                }
+            }
+        }
+        let key = match self.symbols.get(&fact.name) {
+            Some(name) => (name, row.len()),
+            None => return Ok(false),
+        };
+        if !self.relations.get_mut(&key).is_some_and(|relation| relation.remove(&row)) {
+            return Ok(false);
+        }
+        // `:- person(P), !age(P, _).` can break by taking an age out
+        if !self.checks_deferred() {
+            if let Err(e) = self.check_constraints(Some(&[key])) {
+                self.relations.get_mut(&key).unwrap().push(&row);
+                return Err(e);
+            }
+        }
+        self.record(key, &row, false);
+        Ok(true)
+    }
+
+    fn push_rule(&mut self, rule: Rule) -> Result<(), Error> {
+        if self.read_only.contains(&rule.head.name) {
+            return Err(Error::Schema(format!("{} is read only, rules can't add to it", rule.head.name)));
+        }
+        safety::check(&rule)?;
+        let compiled = self.compile_rule(&rule);
+        let head = compiled.head.relation;
+        self.rules.push(compiled);
+        let rules: Vec<&CompiledRule> = self.rules.iter().collect();
+        // not even the well-founded semantics can count something that's still being worked out
+        let cycle = match strata::counting_cycle(&rules) {
+            Some(cycle) => Some((cycle, "counts ")),
+            None => strata::negative_cycle(&rules).filter(|_| !self.well_founded).map(|cycle| (cycle, "depends on !")),
+        };
+        if let Some(((head, read), how)) = cycle {
+            self.rules.pop();
+            return Err(Error::Stratification(format!(
+                "{} {}{}, which depends on {} again",
+                self.symbols.resolve(head.0),
+                how,
+                self.symbols.resolve(read.0),
+                self.symbols.resolve(head.0)
+            )));
+        }
+        if !self.checks_deferred() {
+            if let Err(e) = self.check_constraints(Some(&[head])) {
+                self.rules.pop();
+                return Err(e);
+            }
+        }
+        self.program.push(rule);
+        // a transaction's rules get the version it commits as once it does
+        self.pushed.push(self.version);
         Ok(())
     }
 
-    fn push_rule(&mut self, rule: Rule) -> Result<(), String> {
-        self.rules.push(rule);
+    fn push_constraint(&mut self, constraint: Constraint) -> Result<(), Error> {
+        safety::check_constraint(&constraint)?;
+        let compiled = self.compile_constraint(&constraint);
+        self.constraints.push((constraint, compiled));
+        if !self.checks_deferred() {
+            if let Err(e) = self.check_constraints(None) {
+                self.constraints.pop();
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn begin(&mut self) -> Result<(), Error> {
+        if self.transaction.is_some() {
+            return Err(Error::Schema("a transaction is already open, commit or roll it back first".to_string()));
+        }
+        self.transaction = Some(Box::new(self.save()));
         Ok(())
     }
 
-    fn query(&self, query: Fact) -> Result<Option<Vec<Fact>>, String> {
-        dbg!(&self.rules);
-        if self.rules.iter().any(|r| r.head.name == query.name) {
-            // run the query using the rule's body, which may select from other
-            // relations, compute a join, or recursively call itself or other
-            // rules
-            Ok(Some(self.select_from_rule(query)))
-        } else if self.facts.iter().any(|f| f.name == query.name) {
-            // the query matches a concrete relation
-            // TODO: does datalog's spec allow you to add a fact and then also add a rule so that the rule
-            // yields both that single result plus whatever the other definition adds?
-            if query.vars.iter().all(|v| match v {
-                Fixed(_) => true,
-                Free(_) => false,
-            }) {
-                Ok(Some(self.filter_exact_match(query)))
-            } else {
-                let results = self.select(query);
-                Ok(Some(results))
+    fn commit(&mut self) -> Result<(), Error> {
+        if self.transaction.is_none() {
+            return Err(Error::Schema("no transaction to commit".to_string()));
+        }
+        if let Err(e) = self.check_constraints(None) {
+            self.rollback()?;
+            return Err(e);
+        }
+        // the next version if it changed any facts, otherwise this one
+        let saved = self.transaction.take().unwrap();
+        for pushed in &mut self.pushed[saved.rules.len()..] {
+            *pushed = self.version;
+        }
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<(), Error> {
+        match self.transaction.take() {
+            Some(saved) => {
+                self.restore(*saved);
+                Ok(())
             }
-        } else {
-            println!("halp");
-            Ok(None)
+            None => Err(Error::Schema("no transaction to roll back".to_string())),
         }
     }
+
+    fn query(&self, query: Fact) -> Result<Option<Vec<Fact>>, Error> {
+        Ok(self.query_with_undefined(query)?.map(|(answers, _)| answers))
+    }
+
+    fn declare(&mut self, decl: Declaration) -> Result<(), Error> {
+        match self.declarations.get(&decl.name) {
+            Some(existing) if *existing != decl => Err(Error::Schema(format!(
+                "{} is already declared differently",
+                decl.name
+            ))),
+            _ => {
+                self.declarations.insert(decl.name.clone(), decl);
+                Ok(())
+            }
+        }
+    }
+
+    fn declaration(&self, name: &str) -> Option<&Declaration> {
+        self.declarations.get(name)
+    }
+}
+
+// a fact for a declared relation needs the right number of columns, and numbers where it says number
+fn check_declared(decl: &Declaration, fact: &Fact) -> Result<(), Error> {
+    if decl.columns.len() != fact.vars.len() {
+        return Err(Error::Schema(format!(
+            "{} is declared with {} columns, got {}",
+            decl.name,
+            decl.columns.len(),
+            fact.vars.len()
+        )));
+    }
+    for (column, v) in decl.columns.iter().zip(&fact.vars) {
+        if let (ColumnType::Number, Fixed(s)) = (column.kind, v) {
+            if s.parse::<i64>().is_err() {
+                return Err(Error::Schema(format!(
+                    "{}.{} is a number column, got {:?}",
+                    decl.name, column.name, s
+                )));
+            }
+        }
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -168,10 +1436,7 @@ mod tests {
         > foo(bar)?
         foo(bar).
         */
-        let mut e = RustEngine {
-            facts: vec![],
-            rules: vec![],
-        };
+        let mut e = RustEngine::new();
 
         e.push_fact(fact("foo", vec!["bar"])).unwrap();
         let q = query("foo", vec!["bar"]);
@@ -191,10 +1456,7 @@ mod tests {
         edge(a, b).
         edge(a, c).
         */
-        let mut e = RustEngine {
-            facts: vec![],
-            rules: vec![],
-        };
+        let mut e = RustEngine::new();
         e.push_fact(fact("edge", vec!["a", "b"])).unwrap();
         e.push_fact(fact("edge", vec!["a", "c"])).unwrap();
         e.push_fact(fact("edge", vec!["b", "d"])).unwrap();
@@ -219,10 +1481,7 @@ mod tests {
         edge(c, d).
         edge(j, d).
         */
-        let mut e = RustEngine {
-            facts: vec![],
-            rules: vec![],
-        };
+        let mut e = RustEngine::new();
 
         e.push_fact(fact("edge", vec!["a", "b"])).unwrap();
         e.push_fact(fact("edge", vec!["a", "c"])).unwrap();
@@ -253,10 +1512,7 @@ mod tests {
         bar(c).
         */
 
-        let mut e = RustEngine {
-            facts: vec![],
-            rules: vec![],
-        };
+        let mut e = RustEngine::new();
         e.push_fact(fact("foo", vec!["a"])).unwrap();
         e.push_fact(fact("foo", vec!["b"])).unwrap();
         e.push_fact(fact("foo", vec!["c"])).unwrap();
@@ -268,6 +1524,827 @@ mod tests {
         let r = e.query(q).unwrap().unwrap();
         assert_eq!(r.len(), 3);
     }
+
+    #[test]
+    fn test_rule_joins_two_relations() {
+        /*
+        > parent(alice, bob).
+        > parent(bob, carol).
+        > parent(bob, dave).
+        > grandparent(X, Z) :- parent(X, Y), parent(Y, Z).
+        > grandparent(alice, Q)?
+        grandparent(alice, carol).
+        grandparent(alice, dave).
+        */
+        let mut e = RustEngine::new();
+        e.push_fact(fact("parent", vec!["alice", "bob"])).unwrap();
+        e.push_fact(fact("parent", vec!["bob", "carol"])).unwrap();
+        e.push_fact(fact("parent", vec!["bob", "dave"])).unwrap();
+        e.push_rule(rule(
+            fact("grandparent", vec!["X", "Z"]),
+            vec![fact("parent", vec!["X", "Y"]), fact("parent", vec!["Y", "Z"])],
+        ))
+        .unwrap();
+
+        let r = e.query(query("grandparent", vec!["alice", "Q"])).unwrap().unwrap();
+        assert_eq!(
+            r,
+            vec![
+                fact("grandparent", vec!["alice", "carol"]),
+                fact("grandparent", vec!["alice", "dave"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repeated_free_var_must_match() {
+        /*
+        > edge(a, a).
+        > edge(a, b).
+        > edge(X, X)?
+        edge(a, a).
+        */
+        let mut e = RustEngine::new();
+        e.push_fact(fact("edge", vec!["a", "a"])).unwrap();
+        e.push_fact(fact("edge", vec!["a", "b"])).unwrap();
+
+        let r = e.query(query("edge", vec!["X", "X"])).unwrap().unwrap();
+        assert_eq!(r, vec![fact("edge", vec!["a", "a"])]);
+    }
+
+    #[test]
+    fn test_unknown_constant_matches_nothing() {
+        let mut e = RustEngine::new();
+        e.push_fact(fact("edge", vec!["a", "b"])).unwrap();
+
+        assert_eq!(Some(vec![]), e.query(query("edge", vec!["zzz", "X"])).unwrap());
+        assert_eq!(None, e.query(query("nope", vec!["X"])).unwrap());
+    }
+
+    #[test]
+    fn test_duplicate_facts_are_stored_once() {
+        /*
+        > edge(a, b).
+        > edge(a, b).
+        already known.
+        > edge(a, X)?
+        edge(a, b).
+        */
+        let mut e = RustEngine::new();
+        assert_eq!(Ok(true), e.push_fact(fact("edge", vec!["a", "b"])));
+        assert_eq!(Ok(false), e.push_fact(fact("edge", vec!["a", "b"])));
+
+        let r = e.query(query("edge", vec!["a", "X"])).unwrap().unwrap();
+        assert_eq!(r, vec![fact("edge", vec!["a", "b"])]);
+    }
+
+    #[test]
+    fn test_derived_duplicates_are_dropped() {
+        /*
+        two different ways to derive the same tuple still only give one answer
+        > foo(a, b).
+        > foo(a, c).
+        > bar(X) :- foo(X, Y).
+        > bar(Q)?
+        bar(a).
+        */
+        let mut e = RustEngine::new();
+        e.push_fact(fact("foo", vec!["a", "b"])).unwrap();
+        e.push_fact(fact("foo", vec!["a", "c"])).unwrap();
+        e.push_rule(rule(fact("bar", vec!["X"]), vec![fact("foo", vec!["X", "Y"])]))
+            .unwrap();
+
+        let r = e.query(query("bar", vec!["Q"])).unwrap().unwrap();
+        assert_eq!(r, vec![fact("bar", vec!["a"])]);
+    }
+
+    #[test]
+    fn test_transitive_closure() {
+        /*
+        > edge(a, b).
+        > edge(b, c).
+        > edge(c, a).
+        > edge(c, d).
+        > path(X, Y) :- edge(X, Y).
+        > path(X, Y) :- edge(X, Z), path(Z, Y).
+        > path(d, X)?
+        > path(a, X)?
+        path(a, a).
+        path(a, b).
+        path(a, c).
+        path(a, d).
+        */
+        let mut e = RustEngine::new();
+        e.push_fact(fact("edge", vec!["a", "b"])).unwrap();
+        e.push_fact(fact("edge", vec!["b", "c"])).unwrap();
+        e.push_fact(fact("edge", vec!["c", "a"])).unwrap();
+        e.push_fact(fact("edge", vec!["c", "d"])).unwrap();
+        e.push_rule(rule(fact("path", vec!["X", "Y"]), vec![fact("edge", vec!["X", "Y"])]))
+            .unwrap();
+        e.push_rule(rule(
+            fact("path", vec!["X", "Y"]),
+            vec![fact("edge", vec!["X", "Z"]), fact("path", vec!["Z", "Y"])],
+        ))
+        .unwrap();
+
+        let r = e.query(query("path", vec!["d", "X"])).unwrap().unwrap();
+        assert_eq!(r.len(), 0);
+
+        let mut r = e.query(query("path", vec!["a", "X"])).unwrap().unwrap();
+        r.sort_by(|a, b| format!("{:?}", a).cmp(&format!("{:?}", b)));
+        assert_eq!(
+            r,
+            vec![
+                fact("path", vec!["a", "a"]),
+                fact("path", vec!["a", "b"]),
+                fact("path", vec!["a", "c"]),
+                fact("path", vec!["a", "d"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_join_order_does_not_change_answers() {
+        /*
+        > person(ann). person(bob). person(cat).
+        > likes(ann, bob). likes(bob, cat). likes(cat, cat).
+        > mutual(X, Y) :- person(X), person(Y), likes(X, Y), likes(Y, X), X != Y.
+        > narcissist(X) :- person(X), Y = X, likes(X, Y).
+        > narcissist(Q)?
+        narcissist(cat).
+        */
+        let setup = |e: &mut RustEngine| {
+            for p in &["ann", "bob", "cat"] {
+                e.push_fact(fact("person", vec![p])).unwrap();
+            }
+            e.push_fact(fact("likes", vec!["ann", "bob"])).unwrap();
+            e.push_fact(fact("likes", vec!["bob", "ann"])).unwrap();
+            e.push_fact(fact("likes", vec!["cat", "cat"])).unwrap();
+            e.push_rule(Rule {
+                head: fact("mutual", vec!["X", "Y"]),
+                body: vec![
+                    BodyExpression::Fact(fact("person", vec!["X"])),
+                    BodyExpression::Fact(fact("person", vec!["Y"])),
+                    BodyExpression::Fact(fact("likes", vec!["X", "Y"])),
+                    BodyExpression::Fact(fact("likes", vec!["Y", "X"])),
+                    BodyExpression::Equals(EqualityConstraint {
+                        equals: false,
+                        left: Free("X".to_string()),
+                        right: Free("Y".to_string()),
+                    }),
+                ],
+            })
+            .unwrap();
+            e.push_rule(Rule {
+                head: fact("narcissist", vec!["X"]),
+                body: vec![
+                    BodyExpression::Fact(fact("person", vec!["X"])),
+                    BodyExpression::Equals(EqualityConstraint {
+                        equals: true,
+                        left: Free("Y".to_string()),
+                        right: Free("X".to_string()),
+                    }),
+                    BodyExpression::Fact(fact("likes", vec!["X", "Y"])),
+                ],
+            })
+            .unwrap();
+        };
+        let mut planned = RustEngine::new();
+        setup(&mut planned);
+        let mut written = RustEngine::new();
+        written.pin_written_join_order(true);
+        setup(&mut written);
+
+        for e in &[planned, written] {
+            let r = e.query(query("mutual", vec!["ann", "Q"])).unwrap().unwrap();
+            assert_eq!(r, vec![fact("mutual", vec!["ann", "bob"])]);
+            let r = e.query(query("narcissist", vec!["Q"])).unwrap().unwrap();
+            assert_eq!(r, vec![fact("narcissist", vec!["cat"])]);
+        }
+    }
+
+    #[test]
+    fn test_pinned_order_waits_for_filters_to_be_bound() {
+        /*
+        > q(a). q(b). t(c). t(a).
+        > p(X) :- X != a, q(X).
+        > s(X) :- !q(X), t(X).
+        > p(X)?
+        p(b).
+        > s(X)?
+        s(c).
+        */
+        let program = "q(a). q(b). t(c). t(a). p(X) :- X != a, q(X). s(X) :- !q(X), t(X).";
+        let mut planned = RustEngine::new();
+        let mut written = RustEngine::new();
+        written.pin_written_join_order(true);
+        for e in [&mut planned, &mut written] {
+            for statement in crate::parser::program(program).unwrap() {
+                match statement {
+                    Statement::Fact(f) => e.push_fact(f).map(|_| ()).unwrap(),
+                    Statement::Rule(r) => e.push_rule(r).unwrap(),
+                    other => panic!("{}", other),
+                }
+            }
+        }
+        for e in &[planned, written] {
+            assert_eq!(Ok(Some(vec![fact("p", vec!["b"])])), e.query(query("p", vec!["X"])));
+            assert_eq!(Ok(Some(vec![fact("s", vec!["c"])])), e.query(query("s", vec!["X"])));
+        }
+    }
+
+    fn sorted(mut facts: Vec<Fact>) -> Vec<Fact> {
+        facts.sort_by(|a, b| format!("{:?}", a).cmp(&format!("{:?}", b)));
+        facts
+    }
+
+    // the answers to `q` worked out from all of the queried relation, no magic
+    fn answers_the_slow_way(e: &RustEngine, q: &Fact) -> Vec<Fact> {
+        let atom = e.lookup_atom(q, &mut vec![]).unwrap();
+        let derived = e.evaluate(atom.relation);
+        sorted(
+            derived[&atom.relation]
+                .iter()
+                .filter(|row| matches(&atom.terms, row))
+                .map(|row| e.to_fact(atom.relation, row))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_magic_sets_only_derive_what_the_query_needs() {
+        /*
+        a long chain, asking for what's reachable from near the end of it
+        > edge(n0, n1). edge(n1, n2). ... edge(n49, n50).
+        > path(X, Y) :- edge(X, Y).
+        > path(X, Y) :- edge(X, Z), path(Z, Y).
+        > path(n47, X)?
+        path(n47, n48).
+        path(n47, n49).
+        path(n47, n50).
+        */
+        let mut e = RustEngine::new();
+        for i in 0..50 {
+            e.push_fact(fact("edge", vec![&format!("n{}", i), &format!("n{}", i + 1)]))
+                .unwrap();
+        }
+        e.push_rule(rule(fact("path", vec!["X", "Y"]), vec![fact("edge", vec!["X", "Y"])]))
+            .unwrap();
+        e.push_rule(rule(
+            fact("path", vec!["X", "Y"]),
+            vec![fact("edge", vec!["X", "Z"]), fact("path", vec!["Z", "Y"])],
+        ))
+        .unwrap();
+
+        let q = query("path", vec!["n47", "X"]);
+        let r = sorted(e.query(q.clone()).unwrap().unwrap());
+        assert_eq!(r, answers_the_slow_way(&e, &q));
+        assert_eq!(r.len(), 3);
+
+        let atom = e.lookup_atom(&q, &mut vec![]).unwrap();
+        // path from n47, n48 and n49, which the answers for n47 are built out of
+        let relevant = e.evaluate_goal_directed(&atom);
+        assert_eq!(relevant.len(), 3 + 2 + 1);
+        assert_eq!(e.evaluate(atom.relation)[&atom.relation].len(), 50 * 51 / 2);
+    }
+
+    #[test]
+    fn test_magic_sets_agree_with_full_evaluation() {
+        /*
+        left recursion, constants in either position, stored facts under a
+        derived name and same generation
+        > par(c1, p1). par(c2, p1). par(c3, p2). par(p1, g). par(p2, g).
+        > sg(X, X) :- par(X, P).
+        > sg(X, Y) :- par(X, XP), sg(XP, YP), par(Y, YP).
+        > reach(X, Y) :- reach(X, Z), par(Z, Y).
+        > reach(X, Y) :- par(X, Y).
+        > reach(zz, c1).
+        */
+        let mut e = RustEngine::new();
+        for (c, p) in &[("c1", "p1"), ("c2", "p1"), ("c3", "p2"), ("p1", "g"), ("p2", "g")] {
+            e.push_fact(fact("par", vec![c, p])).unwrap();
+        }
+        e.push_rule(rule(fact("sg", vec!["X", "X"]), vec![fact("par", vec!["X", "P"])]))
+            .unwrap();
+        e.push_rule(rule(
+            fact("sg", vec!["X", "Y"]),
+            vec![
+                fact("par", vec!["X", "XP"]),
+                fact("sg", vec!["XP", "YP"]),
+                fact("par", vec!["Y", "YP"]),
+            ],
+        ))
+        .unwrap();
+        e.push_rule(rule(
+            fact("reach", vec!["X", "Y"]),
+            vec![fact("reach", vec!["X", "Z"]), fact("par", vec!["Z", "Y"])],
+        ))
+        .unwrap();
+        e.push_rule(rule(fact("reach", vec!["X", "Y"]), vec![fact("par", vec!["X", "Y"])]))
+            .unwrap();
+        e.push_fact(fact("reach", vec!["zz", "c1"])).unwrap();
+
+        for q in &[
+            query("sg", vec!["c1", "X"]),
+            query("sg", vec!["X", "c3"]),
+            query("sg", vec!["c2", "c3"]),
+            query("reach", vec!["c1", "X"]),
+            query("reach", vec!["X", "g"]),
+            query("reach", vec!["zz", "X"]),
+        ] {
+            let r = sorted(e.query(q.clone()).unwrap().unwrap());
+            assert_eq!(r, answers_the_slow_way(&e, q), "{:?}", q);
+        }
+        assert_eq!(
+            sorted(e.query(query("reach", vec!["zz", "X"])).unwrap().unwrap()),
+            vec![
+                fact("reach", vec!["zz", "c1"]),
+                fact("reach", vec!["zz", "g"]),
+                fact("reach", vec!["zz", "p1"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parallel_matches_sequential() {
+        /*
+        a random graph big enough that joins over it get partitioned
+        > edge(n17, n4). edge(n90, n33). ...
+        > path(X, Y) :- edge(X, Y).
+        > path(X, Y) :- path(X, Z), edge(Z, Y).
+        > two_hops(X, Y) :- edge(X, Z), edge(Z, Y).
+        */
+        let build = |threads| {
+            let mut e = RustEngine::new();
+            e.set_threads(threads);
+            let mut seed: u64 = 42;
+            let mut next = || {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                (seed >> 33) % 80
+            };
+            for _ in 0..1200 {
+                let (a, b) = (next(), next());
+                e.push_fact(fact("edge", vec![&format!("n{}", a), &format!("n{}", b)]))
+                    .unwrap();
+            }
+            e.push_rule(rule(fact("path", vec!["X", "Y"]), vec![fact("edge", vec!["X", "Y"])]))
+                .unwrap();
+            e.push_rule(rule(
+                fact("path", vec!["X", "Y"]),
+                vec![fact("path", vec!["X", "Z"]), fact("edge", vec!["Z", "Y"])],
+            ))
+            .unwrap();
+            e.push_rule(rule(
+                fact("two_hops", vec!["X", "Y"]),
+                vec![fact("edge", vec!["X", "Z"]), fact("edge", vec!["Z", "Y"])],
+            ))
+            .unwrap();
+            e
+        };
+        let sequential = build(1);
+        let parallel = build(4);
+        for q in &[
+            query("path", vec!["X", "Y"]),
+            query("path", vec!["n3", "Y"]),
+            query("two_hops", vec!["X", "Y"]),
+        ] {
+            let expected = sorted(sequential.query(q.clone()).unwrap().unwrap());
+            assert!(!expected.is_empty());
+            assert_eq!(expected, sorted(parallel.query(q.clone()).unwrap().unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_unsafe_rules_are_refused() {
+        /*
+        > edge(a, b).
+        > anything(X, Y) :- edge(X, Z).
+        Error: unsafe: Y in anything(X, Y) :- edge(X, Z). isn't bound by a positive body atom
+        > loop(X) :- edge(X, Y), Y = Z, Z = X.
+        > lonely(X) :- edge(X, Y), !edge(Y, Z).
+        Error: unsafe: Z in ...
+        */
+        let mut e = RustEngine::new();
+        e.push_fact(fact("edge", vec!["a", "b"])).unwrap();
+        match e.push_rule(rule(fact("anything", vec!["X", "Y"]), vec![fact("edge", vec!["X", "Z"])])) {
+            Err(Error::Safety(m)) => assert!(m.starts_with("Y in anything(X, Y)"), "{}", m),
+            x => panic!("{:?}", x),
+        }
+        assert!(e.push_fact(fact("edge", vec!["a", "X"])).is_err());
+
+        let equal = |left: &str, right: &str| {
+            BodyExpression::Equals(EqualityConstraint {
+                equals: true,
+                left: v(vec![left]).remove(0),
+                right: v(vec![right]).remove(0),
+            })
+        };
+        let chained = Rule {
+            head: fact("loop", vec!["X"]),
+            body: vec![equal("Y", "Z"), equal("Z", "X"), BodyExpression::Fact(fact("edge", vec!["X", "Y"]))],
+        };
+        assert_eq!(Ok(()), e.push_rule(chained));
+        let negated = Rule {
+            head: fact("lonely", vec!["X"]),
+            body: vec![
+                BodyExpression::Fact(fact("edge", vec!["X", "Y"])),
+                BodyExpression::Not(fact("edge", vec!["Y", "Z"])),
+            ],
+        };
+        assert!(e.push_rule(negated).is_err());
+        assert_eq!(1, e.written_rules().len());
+    }
+
+    #[test]
+    fn test_stratified_negation() {
+        /*
+        > edge(a, b). edge(b, c). node(a). node(b). node(c). node(d).
+        > path(X, Y) :- edge(X, Y).
+        > path(X, Y) :- path(X, Z), edge(Z, Y).
+        > source(X) :- node(X), !has_parent(X).
+        > has_parent(Y) :- edge(X, Y).
+        > unreachable(X) :- node(X), !path(a, X).
+        > unreachable(X)?
+        unreachable(a).
+        unreachable(d).
+        > win(X) :- edge(X, Y), !win(Y).
+        Error: not stratifiable: win depends on !win, which depends on win again
+        */
+        let mut e = RustEngine::new();
+        for (a, b) in &[("a", "b"), ("b", "c")] {
+            e.push_fact(fact("edge", vec![a, b])).unwrap();
+        }
+        for n in &["a", "b", "c", "d"] {
+            e.push_fact(fact("node", vec![n])).unwrap();
+        }
+        e.push_rule(rule(fact("path", vec!["X", "Y"]), vec![fact("edge", vec!["X", "Y"])]))
+            .unwrap();
+        e.push_rule(rule(
+            fact("path", vec!["X", "Y"]),
+            vec![fact("path", vec!["X", "Z"]), fact("edge", vec!["Z", "Y"])],
+        ))
+        .unwrap();
+        let without = |head: Fact, positive: Fact, negated: Fact| Rule {
+            head,
+            body: vec![BodyExpression::Fact(positive), BodyExpression::Not(negated)],
+        };
+        e.push_rule(without(fact("source", vec!["X"]), fact("node", vec!["X"]), fact("has_parent", vec!["X"])))
+            .unwrap();
+        e.push_rule(rule(fact("has_parent", vec!["Y"]), vec![fact("edge", vec!["X", "Y"])]))
+            .unwrap();
+        e.push_rule(without(fact("unreachable", vec!["X"]), fact("node", vec!["X"]), fact("path", vec!["a", "X"])))
+            .unwrap();
+
+        assert_eq!(
+            vec![fact("source", vec!["a"]), fact("source", vec!["d"])],
+            sorted(e.query(query("source", vec!["X"])).unwrap().unwrap())
+        );
+        assert_eq!(
+            vec![fact("unreachable", vec!["a"]), fact("unreachable", vec!["d"])],
+            sorted(e.query(query("unreachable", vec!["X"])).unwrap().unwrap())
+        );
+        assert_eq!(1, e.query(query("unreachable", vec!["d"])).unwrap().unwrap().len());
+
+        let win = without(fact("win", vec!["X"]), fact("edge", vec!["X", "Y"]), fact("win", vec!["Y"]));
+        assert_eq!(
+            Err(Error::Stratification("win depends on !win, which depends on win again".to_string())),
+            e.push_rule(win)
+        );
+        // the refused rule is gone, everything else still answers
+        assert_eq!(Ok(None), e.query(query("win", vec!["X"])));
+        assert_eq!(2, e.query(query("path", vec!["a", "X"])).unwrap().unwrap().len());
+    }
+
+    #[test]
+    fn test_count() {
+        /*
+        > node(a). node(b). node(c). edge(a, b). edge(a, c). edge(b, c).
+        > out(X, N) :- node(X), N = count : { edge(X, _) }.
+        > out(X, N)?
+        out(a, 2).
+        out(b, 1).
+        out(c, 0).
+        > size(N) :- N = count : { grow(_) }.
+        > grow(N) :- size(N).
+        Error: not stratifiable: size counts grow, which depends on size again
+        */
+        let mut e = RustEngine::new();
+        e.set_well_founded(true);
+        for statement in crate::parser::program(
+            "node(a). node(b). node(c). edge(a, b). edge(a, c). edge(b, c).
+            out(X, N) :- node(X), N = count : { edge(X, _) }.
+            size(N) :- N = count : { grow(_) }.",
+        )
+        .unwrap()
+        {
+            match statement {
+                Statement::Fact(f) => e.push_fact(f).map(|_| ()).unwrap(),
+                Statement::Rule(r) => e.push_rule(r).unwrap(),
+                other => panic!("{}", other),
+            }
+        }
+        assert_eq!(
+            vec![fact("out", vec!["a", "2"]), fact("out", vec!["b", "1"]), fact("out", vec!["c", "0"])],
+            sorted(e.query(query("out", vec!["X", "N"])).unwrap().unwrap())
+        );
+        // bound arguments don't get a magic set rewrite, which would count too little
+        assert_eq!(Ok(Some(vec![fact("out", vec!["a", "2"])])), e.query(query("out", vec!["a", "N"])));
+        assert_eq!(Ok(Some(vec![fact("out", vec!["c", "0"])])), e.query(query("out", vec!["X", "0"])));
+        assert_eq!(Ok(Some(vec![fact("size", vec!["0"])])), e.query(query("size", vec!["N"])));
+
+        // not even the well-founded semantics can count what's still being worked out
+        assert_eq!(
+            Err(Error::Stratification("size counts grow, which depends on size again".to_string())),
+            e.push_rule(rule(fact("grow", vec!["N"]), vec![fact("size", vec!["N"])]))
+        );
+    }
+
+    #[test]
+    fn test_well_founded_negation() {
+        /*
+        a position is won when there's a move to one that isn't. a and b can
+        move back and forth forever, so neither is won or lost
+        > move(a, b). move(b, a). move(b, c). move(c, d).
+        > win(X) :- move(X, Y), !win(Y).
+        > win(X)?
+        win(c).
+        win(a). % undefined
+        win(b). % undefined
+        */
+        let mut e = RustEngine::new();
+        e.set_well_founded(true);
+        for (a, b) in &[("a", "b"), ("b", "a"), ("b", "c"), ("c", "d")] {
+            e.push_fact(fact("move", vec![a, b])).unwrap();
+        }
+        e.push_rule(Rule {
+            head: fact("win", vec!["X"]),
+            body: vec![
+                BodyExpression::Fact(fact("move", vec!["X", "Y"])),
+                BodyExpression::Not(fact("win", vec!["Y"])),
+            ],
+        })
+        .unwrap();
+
+        let (won, undefined) = e.query_with_undefined(query("win", vec!["X"])).unwrap().unwrap();
+        assert_eq!(vec![fact("win", vec!["c"])], won);
+        assert_eq!(vec![fact("win", vec!["a"]), fact("win", vec!["b"])], sorted(undefined));
+        assert_eq!(Ok(Some(vec![])), e.query(query("win", vec!["d"])));
+        assert_eq!(
+            Ok(Some((vec![], vec![fact("win", vec!["b"])]))),
+            e.query_with_undefined(query("win", vec!["b"]))
+        );
+
+        // programs that stratify don't notice
+        e.push_rule(rule(fact("moves", vec!["X"]), vec![fact("move", vec!["X", "Y"])])).unwrap();
+        assert_eq!(Ok(Some((vec![fact("moves", vec!["c"])], vec![]))), e.query_with_undefined(query("moves", vec!["c"])));
+    }
+
+    #[test]
+    fn test_constraints() {
+        /*
+        > person(ann). person(bob). age(ann, 40).
+        > manages(ann, bob).
+        > :- manages(X, X).
+        > :- person(P), !age(P, _).
+        Error: constraint violated: :- person(P), !age(P, _). by person(bob), !age(bob, _)
+        */
+        let mut e = RustEngine::new();
+        let mut run = |text: &str| -> Result<(), Error> {
+            for statement in crate::parser::program(text)? {
+                match statement {
+                    Statement::Fact(f) => e.push_fact(f).map(|_| ())?,
+                    Statement::Rule(r) => e.push_rule(r)?,
+                    Statement::Constraint(c) => e.push_constraint(c)?,
+                    other => panic!("{}", other),
+                }
+            }
+            Ok(())
+        };
+        run("person(ann). person(bob). age(ann, 40). manages(ann, bob).").unwrap();
+        run(":- manages(X, X).").unwrap();
+        assert_eq!(
+            Err(Error::Constraint(":- manages(X, X). by manages(bob, bob)".to_string())),
+            run("manages(bob, bob).")
+        );
+        assert_eq!(
+            Err(Error::Constraint(":- person(P), !age(P, _). by person(bob), !age(bob, _)".to_string())),
+            run(":- person(P), !age(P, _).")
+        );
+        run("age(bob, 30).").unwrap();
+        run(":- person(P), !age(P, _).").unwrap();
+
+        // rules get checked against them too
+        run("boss(X, Y) :- manages(X, Y). boss(X, Z) :- boss(X, Y), manages(Y, Z).").unwrap();
+        run(":- boss(X, Y), boss(Y, X).").unwrap();
+        assert!(run("boss(X, Y) :- manages(Y, X).").is_err());
+        assert!(matches!(run("manages(bob, ann)."), Err(Error::Constraint(_))));
+        assert!(matches!(run("person(cy)."), Err(Error::Constraint(_))));
+
+        // whatever got refused isn't there
+        assert_eq!(Ok(Some(vec![fact("manages", vec!["ann", "bob"])])), e.query(query("manages", vec!["X", "Y"])));
+        assert_eq!(2, e.written_rules().len());
+        assert_eq!(3, e.constraints().len());
+        assert_eq!(Ok(Some(vec![])), e.query(query("person", vec!["cy"])));
+    }
+
+    #[test]
+    fn test_transactions() {
+        /*
+        a batch that breaks a constraint doesn't get in halfway
+        > :- manages(X, X).
+        > :begin
+        > manages(ann, bob).
+        > manages(bob, bob).
+        > boss(X, Y) :- manages(X, Y).
+        > :commit
+        Error: constraint violated: :- manages(X, X). by manages(bob, bob), rolled back
+        > boss(X, Y)?
+        */
+        let mut e = RustEngine::new();
+        e.push_constraint(Constraint {
+            body: vec![BodyExpression::Fact(fact("manages", vec!["X", "X"]))],
+            line: 0,
+            file: None,
+        })
+        .unwrap();
+        let boss = || rule(fact("boss", vec!["X", "Y"]), vec![fact("manages", vec!["X", "Y"])]);
+
+        e.begin().unwrap();
+        assert!(e.begin().is_err());
+        e.push_fact(fact("manages", vec!["ann", "bob"])).unwrap();
+        // not checked until the commit
+        e.push_fact(fact("manages", vec!["bob", "bob"])).unwrap();
+        e.push_rule(boss()).unwrap();
+        assert_eq!(2, e.query(query("boss", vec!["X", "Y"])).unwrap().unwrap().len());
+        assert_eq!(
+            Err(Error::Constraint(":- manages(X, X). by manages(bob, bob)".to_string())),
+            e.commit()
+        );
+        assert!(!e.in_transaction());
+        assert_eq!(Ok(None), e.query(query("boss", vec!["X", "Y"])));
+        assert_eq!(Ok(None), e.query(query("manages", vec!["X", "Y"])));
+
+        e.begin().unwrap();
+        e.push_fact(fact("manages", vec!["ann", "bob"])).unwrap();
+        e.push_rule(boss()).unwrap();
+        e.commit().unwrap();
+        e.begin().unwrap();
+        e.push_fact(fact("manages", vec!["bob", "cy"])).unwrap();
+        e.rollback().unwrap();
+        assert_eq!(Ok(Some(vec![fact("boss", vec!["ann", "bob"])])), e.query(query("boss", vec!["X", "Y"])));
+        assert_eq!(Err(Error::Schema("no transaction to roll back".to_string())), e.rollback());
+        assert!(e.commit().is_err());
+    }
+
+    #[test]
+    fn test_batches() {
+        let mut e = RustEngine::new();
+        e.push_constraint(Constraint {
+            body: vec![BodyExpression::Fact(fact("manages", vec!["X", "X"]))],
+            line: 0,
+            file: None,
+        })
+        .unwrap();
+        let boss = || rule(fact("boss", vec!["X", "Y"]), vec![fact("manages", vec!["X", "Y"])]);
+
+        // checked once at the end, and then it's all or nothing
+        let loaded = e.batch(|e| {
+            e.push_fact(fact("manages", vec!["ann", "bob"])).unwrap();
+            e.push_fact(fact("manages", vec!["bob", "bob"])).unwrap();
+            e.push_rule(boss()).unwrap();
+        });
+        assert_eq!(Err(Error::Constraint(":- manages(X, X). by manages(bob, bob)".to_string())), loaded);
+        assert_eq!(Ok(None), e.query(query("manages", vec!["X", "Y"])));
+        assert!(e.rules().is_empty());
+        assert_eq!(0, e.version());
+
+        // unlike a transaction every fact still gets a version of its own
+        e.batch(|e| {
+            e.push_fact(fact("manages", vec!["ann", "bob"])).unwrap();
+            e.push_fact(fact("manages", vec!["bob", "cy"])).unwrap();
+        })
+        .unwrap();
+        assert_eq!(2, e.version());
+
+        // inside a transaction the commit does the checking
+        e.begin().unwrap();
+        assert_eq!(Ok(Ok(true)), e.batch(|e| e.push_fact(fact("manages", vec!["cy", "cy"]))));
+        assert!(e.commit().is_err());
+        assert_eq!(2, e.query(query("manages", vec!["X", "Y"])).unwrap().unwrap().len());
+    }
+
+    #[test]
+    fn test_versions() {
+        /*
+        > access(ann, payroll).
+        > access(bob, payroll).
+        > ~access(bob, payroll).
+        > access(U, payroll) as of 2?
+        access(ann, payroll).
+        access(bob, payroll).
+        */
+        let mut e = RustEngine::new();
+        assert_eq!(0, e.version());
+        e.push_rule(rule(fact("user", vec!["U"]), vec![fact("access", vec!["U", "R"])])).unwrap();
+        e.push_fact(fact("access", vec!["ann", "payroll"])).unwrap();
+        e.push_fact(fact("access", vec!["bob", "payroll"])).unwrap();
+        e.push_fact(fact("access", vec!["bob", "payroll"])).unwrap();
+        assert_eq!(2, e.version());
+        assert_eq!(Ok(true), e.retract_fact(fact("access", vec!["bob", "payroll"])));
+        assert_eq!(Ok(false), e.retract_fact(fact("access", vec!["bob", "payroll"])));
+        assert_eq!(Ok(false), e.retract_fact(fact("access", vec!["nobody", "payroll"])));
+        assert_eq!(3, e.version());
+
+        let q = query("access", vec!["U", "payroll"]);
+        let then = e.as_of(2).unwrap();
+        assert_eq!(2, then.query(q.clone()).unwrap().unwrap().len());
+        assert_eq!(1, e.query(q.clone()).unwrap().unwrap().len());
+        assert_eq!(Ok(Some(vec![])), e.as_of(0).unwrap().query(q.clone()));
+        assert!(e.as_of(4).is_err());
+
+        // derived relations get worked out from the facts as they were
+        let (added, removed) = e.diff(query("user", vec!["U"]), 1, 3).unwrap();
+        assert!(added.is_empty());
+        assert!(removed.is_empty());
+        let (added, removed) = e.diff(query("user", vec!["U"]), 2, 3).unwrap();
+        assert!(added.is_empty());
+        assert_eq!(vec![fact("user", vec!["bob"])], removed);
+        // and from the rules there were then
+        e.push_rule(rule(fact("admin", vec!["U"]), vec![fact("access", vec!["U", "payroll"])])).unwrap();
+        assert_eq!(Ok(None), e.as_of(2).unwrap().query(query("admin", vec!["U"])));
+        assert_eq!(Ok(Some(vec![fact("admin", vec!["ann"])])), e.query(query("admin", vec!["U"])));
+
+        // a transaction is one version, and rolling it back forgets it
+        e.begin().unwrap();
+        e.push_fact(fact("access", vec!["cy", "payroll"])).unwrap();
+        e.push_fact(fact("access", vec!["di", "payroll"])).unwrap();
+        e.commit().unwrap();
+        assert_eq!(4, e.version());
+        e.begin().unwrap();
+        e.retract_fact(fact("access", vec!["cy", "payroll"])).unwrap();
+        e.rollback().unwrap();
+        assert_eq!(4, e.version());
+
+        // a transaction of nothing but rules doesn't make a version, its
+        // rules count from the current one
+        e.begin().unwrap();
+        e.push_rule(rule(fact("staff", vec!["U"]), vec![fact("access", vec!["U", "R"])])).unwrap();
+        e.commit().unwrap();
+        assert_eq!(4, e.version());
+        let staff = e.query(query("staff", vec!["U"])).unwrap().unwrap();
+        assert_eq!(3, staff.len());
+        assert_eq!(Ok(Some(staff)), e.as_of(e.version()).unwrap().query(query("staff", vec!["U"])));
+        assert_eq!(Ok(None), e.as_of(3).unwrap().query(query("staff", vec!["U"])));
+        // and with facts too, from the version it commits as
+        e.begin().unwrap();
+        e.push_rule(rule(fact("guest", vec!["U"]), vec![fact("access", vec!["U", "lobby"])])).unwrap();
+        e.push_fact(fact("access", vec!["ed", "lobby"])).unwrap();
+        e.commit().unwrap();
+        assert_eq!(Ok(None), e.as_of(4).unwrap().query(query("guest", vec!["U"])));
+        assert_eq!(Ok(Some(vec![fact("guest", vec!["ed"])])), e.as_of(5).unwrap().query(query("guest", vec!["U"])));
+        let (added, _) = e.diff(query("user", vec!["U"]), 3, 4).unwrap();
+        assert_eq!(vec![fact("user", vec!["cy"]), fact("user", vec!["di"])], sorted(added));
+    }
+
+    // the old representation, one `Fact` per tuple, kept around to compare against
+    fn fact_heap_bytes(f: &Fact) -> usize {
+        mem::size_of::<Fact>()
+            + f.name.capacity()
+            + f.vars.capacity() * mem::size_of::<Variable>()
+            + f.vars
+                .iter()
+                .map(|v| match v {
+                    Fixed(s) | Free(s) => s.capacity(),
+                })
+                .sum::<usize>()
+    }
+
+    #[test]
+    fn test_interned_storage_is_smaller() {
+        // how fast they are is for `cargo bench -- point-query`
+        // every edge a different one, so nothing gets deduplicated away
+        let nodes = 200;
+        let edges: Vec<Fact> = (0..20_000)
+            .map(|i| {
+                let src = format!("node{}", i % nodes);
+                let dst = format!("node{}", i / nodes);
+                fact("edge", vec![&src, &dst])
+            })
+            .collect();
+        let unpacked: usize = edges.iter().map(fact_heap_bytes).sum();
+        let q = query("edge", vec!["node42", "X"]);
+        let scanned = edges
+            .iter()
+            .filter(|f| f.name == q.name && f.vars[0] == q.vars[0])
+            .count();
+
+        let mut e = RustEngine::new();
+        for f in edges {
+            e.push_fact(f).unwrap();
+        }
+        assert_eq!(scanned, e.query(q).unwrap().unwrap().len());
+        assert!(e.heap_bytes() * 2 < unpacked, "{} bytes interned, {} as facts", e.heap_bytes(), unpacked);
+    }
 }
 
 // TODO: these are just some tests to play around with rusqlite
@@ -0,0 +1,146 @@
+#![allow(unused_imports, dead_code)]
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::mem;
+
+/*
+ * turns relation names and constants into small integer ids so the engine
+ * never has to clone or compare strings while evaluating
+ */
+
+/// An interned string. Two symbols from the same `Interner` are equal exactly
+/// when the strings they were made from are equal.
+///
+/// Whole numbers like `42` are symbols of their own with the top bit set, so
+/// evaluation can make new ones, like a count, without a table to add them to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Sym(u32);
+
+const NUMBER: u32 = 1 << 31;
+
+impl Sym {
+    /// The symbol for `n`, the same one interning its digits gives. None if
+    /// it's too big to have one.
+    pub fn number(n: usize) -> Option<Sym> {
+        u32::try_from(n).ok().filter(|n| *n < NUMBER).map(|n| Sym(n | NUMBER))
+    }
+
+    // the number a symbol stands for, if it's one of those
+    fn as_number(self) -> Option<u32> {
+        (self.0 & NUMBER != 0).then_some(self.0 & !NUMBER)
+    }
+
+    // `name`'s number symbol, if it's written the one way `number` would write it
+    fn parse(name: &str) -> Option<Sym> {
+        let canonical = name == "0" || (!name.starts_with('0') && name.bytes().all(|b| b.is_ascii_digit()));
+        if !canonical {
+            return None;
+        }
+        name.parse::<usize>().ok().and_then(Sym::number)
+    }
+
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    /// A symbol that isn't in any table, for names that only exist during an
+    /// evaluation (like the relations magic sets invents) and never get
+    /// resolved. Callers pick indexes past `Interner::len` so they can't clash.
+    pub fn scratch(index: usize) -> Sym {
+        Sym(index as u32)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Interner {
+    ids: HashMap<String, Sym>,
+    names: Vec<String>,
+}
+
+impl Interner {
+    pub fn new() -> Interner {
+        Interner::default()
+    }
+
+    /// returns the symbol for `name`, allocating a new one the first time it is seen
+    pub fn intern(&mut self, name: &str) -> Sym {
+        if let Some(sym) = Sym::parse(name) {
+            return sym;
+        }
+        if let Some(sym) = self.ids.get(name) {
+            return *sym;
+        }
+        let sym = Sym(self.names.len() as u32);
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), sym);
+        sym
+    }
+
+    /// looks up a symbol without allocating one. a query that mentions a
+    /// constant the engine has never seen can't match anything, so there's no
+    /// point growing the table for it
+    pub fn get(&self, name: &str) -> Option<Sym> {
+        Sym::parse(name).or_else(|| self.ids.get(name).cloned())
+    }
+
+    pub fn resolve(&self, sym: Sym) -> Cow<'_, str> {
+        match sym.as_number() {
+            Some(n) => Cow::Owned(n.to_string()),
+            None => Cow::Borrowed(&self.names[sym.index()]),
+        }
+    }
+
+    /// how many names are in the table, numbers aren't
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// rough count of the bytes this table holds on the heap
+    pub fn heap_bytes(&self) -> usize {
+        let strings: usize = self.names.iter().map(|n| n.capacity()).sum();
+        // every name is stored twice, once as the map key and once in `names`
+        2 * strings
+            + self.names.capacity() * mem::size_of::<String>()
+            + self.ids.capacity() * (mem::size_of::<String>() + mem::size_of::<Sym>())
+    }
+}
+
+#[test]
+fn test_intern_round_trip() {
+    let mut i = Interner::new();
+    let a = i.intern("a");
+    let b = i.intern("b");
+    assert_ne!(a, b);
+    assert_eq!(a, i.intern("a"));
+    assert_eq!("a", i.resolve(a));
+    assert_eq!("b", i.resolve(b));
+    assert_eq!(2, i.len());
+}
+
+#[test]
+fn test_get_does_not_allocate() {
+    let mut i = Interner::new();
+    i.intern("a");
+    assert_eq!(None, i.get("nope"));
+    assert_eq!(1, i.len());
+}
+
+#[test]
+fn test_numbers_need_no_table() {
+    let mut i = Interner::new();
+    let n = i.intern("42");
+    assert_eq!(Sym::number(42), Some(n));
+    assert_eq!(Some(n), i.get("42"));
+    assert_eq!("42", i.resolve(n));
+    // written any other way it's just a name
+    let padded = i.intern("007");
+    assert_ne!(Sym::number(7), Some(padded));
+    assert_eq!("007", i.resolve(padded));
+    assert_eq!(1, i.len());
+}
@@ -0,0 +1,273 @@
+#![allow(unused_imports, dead_code)]
+extern crate serde_json;
+
+use serde_json::{Map, Value};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+/*
+ * json in and out: facts from json lines files, one tuple per line, and query
+ * answers as a json array of bindings
+ *
+ *   .input person(format="jsonl", filename="people.jsonl")
+ *   {"name": "alice", "age": 30}
+ *   ["bob", 41]
+ *
+ *   person(N, 30)?
+ *   [{"N":"alice"}]
+ */
+use crate::ast::{ColumnType, Declaration, Directive, Fact, Variable};
+use crate::engine::DatalogEngine;
+use crate::error::Error;
+
+/// Where an `.input` or `.output` with `format="jsonl"` goes, by default
+/// `<relation>.jsonl`.
+pub fn path(d: &Directive) -> Result<PathBuf, Error> {
+    let mut path = PathBuf::from(format!("{}.jsonl", d.relation));
+    for (key, value) in &d.params {
+        match key.as_str() {
+            "format" => {}
+            "filename" => path = PathBuf::from(value),
+            _ => return Err(Error::Schema(format!("unknown parameter {} for {}", key, d.relation))),
+        }
+    }
+    Ok(path)
+}
+
+/// Loads a json lines file as facts of `relation`, returning how many new facts that made.
+pub fn import(engine: &mut dyn DatalogEngine, relation: &str, path: &Path) -> Result<usize, Error> {
+    let file = File::open(path).map_err(|e| Error::io(&path.display().to_string(), e))?;
+    import_from(engine, relation, file, &path.display().to_string())
+}
+
+/// Like `import` but from anything readable, `source` is only used in errors.
+///
+/// Every non-blank line is one tuple: either an array holding the columns in
+/// order, or an object keyed by the column names of the relation's `.decl`.
+/// Values are strings or integers, and number columns take either.
+pub fn import_from<R: Read>(
+    engine: &mut dyn DatalogEngine,
+    relation: &str,
+    input: R,
+    source: &str,
+) -> Result<usize, Error> {
+    let decl = engine.declaration(relation).cloned();
+    let mut added = 0;
+    for (n, line) in BufReader::new(input).lines().enumerate() {
+        let place = format!("{}:{}", source, n + 1);
+        let line = line.map_err(|e| Error::io(&place, e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value: Value = serde_json::from_str(&line).map_err(|e| Error::io(&place, e))?;
+        let at = |e: String| Error::Schema(e).context(&place);
+        let values = match value {
+            Value::Array(values) => values,
+            Value::Object(mut object) => match &decl {
+                Some(decl) => {
+                    let mut values = vec![];
+                    for column in &decl.columns {
+                        match object.remove(&column.name) {
+                            Some(v) => values.push(v),
+                            None => return Err(at(format!("no {} in {}", column.name, line))),
+                        }
+                    }
+                    values
+                }
+                None => return Err(at(format!("declare {} with .decl to load objects", relation))),
+            },
+            _ => return Err(at(format!("expected an array or an object, got {}", line))),
+        };
+        let mut vars = Vec::with_capacity(values.len());
+        for (i, v) in values.iter().enumerate() {
+            let kind = decl
+                .as_ref()
+                .and_then(|d| d.columns.get(i))
+                .map_or(ColumnType::Symbol, |c| c.kind);
+            vars.push(Variable::Fixed(constant(v, kind).map_err(&at)?));
+        }
+        let fact = Fact {
+            name: relation.to_string(),
+            vars,
+        };
+        if engine.push_fact(fact).map_err(|e| e.context(&place))? {
+            added += 1;
+        }
+    }
+    Ok(added)
+}
+
+fn constant(v: &Value, kind: ColumnType) -> Result<String, String> {
+    match (v, kind) {
+        (Value::String(s), ColumnType::Symbol) => Ok(s.clone()),
+        (Value::Number(n), _) if n.is_i64() => Ok(n.to_string()),
+        (Value::String(s), ColumnType::Number) => match s.trim().parse::<i64>() {
+            Ok(n) => Ok(n.to_string()),
+            Err(_) => Err(format!("{:?} is not a number", s)),
+        },
+        _ => Err(format!("{} can't be a constant", v)),
+    }
+}
+
+// numbers in number columns go out as json numbers, everything else as strings
+fn value(s: &str, kind: Option<ColumnType>) -> Value {
+    match (kind, s.parse::<i64>()) {
+        (Some(ColumnType::Number), Ok(n)) => Value::from(n),
+        _ => Value::from(s),
+    }
+}
+
+/// The answers to `query` as a json array with one object per answer,
+/// binding each of the query's variables. A query without variables gets
+/// `[{}]` when it holds and `[]` when it doesn't.
+///
+/// Answers that are undefined under the well-founded semantics come after
+/// the rest, marked `"undefined": true`. Variables are capitalized, so the
+/// key can't clash with one.
+pub fn bindings(engine: &dyn DatalogEngine, query: &Fact, answers: &[Fact], undefined: &[Fact]) -> Value {
+    let decl = engine.declaration(&query.name);
+    let kind = |i: usize| decl.and_then(|d| d.columns.get(i)).map(|c| c.kind);
+    let object = |answer: &Fact| {
+        let mut object = Map::new();
+        for (i, (asked, got)) in query.vars.iter().zip(&answer.vars).enumerate() {
+            if let (Variable::Free(name), Variable::Fixed(s)) = (asked, got) {
+                if asked.is_wildcard() {
+                    continue;
+                }
+                object.insert(name.clone(), value(s, kind(i)));
+            }
+        }
+        object
+    };
+    let undefined = undefined.iter().map(|answer| {
+        let mut object = object(answer);
+        object.insert("undefined".to_string(), Value::Bool(true));
+        object
+    });
+    Value::Array(answers.iter().map(object).chain(undefined).map(Value::Object).collect())
+}
+
+/// Writes every tuple of `relation` to `path` as json lines, see `export_to`.
+pub fn export(engine: &dyn DatalogEngine, relation: &str, path: &Path) -> Result<usize, Error> {
+    let file = File::create(path).map_err(|e| Error::io(&path.display().to_string(), e))?;
+    export_to(engine, relation, file).map_err(|e| match e {
+        Error::Io { message, .. } => Error::io(&path.display().to_string(), message),
+        e => e,
+    })
+}
+
+/// Writes every tuple of `relation` as one object per line, keyed by the
+/// column names in its `.decl`, sorted so the same facts give the same file.
+pub fn export_to<W: Write>(engine: &dyn DatalogEngine, relation: &str, mut output: W) -> Result<usize, Error> {
+    let decl = match engine.declaration(relation) {
+        Some(d) => d,
+        None => return Err(Error::Schema(format!("declare {} with .decl before writing it out", relation))),
+    };
+    let query = Fact {
+        name: relation.to_string(),
+        vars: (0..decl.columns.len())
+            .map(|i| Variable::Free(format!("C{}", i)))
+            .collect(),
+    };
+    let mut rows: Vec<Vec<String>> = engine
+        .query(query)?
+        .unwrap_or_default()
+        .into_iter()
+        .map(|f| {
+            f.vars
+                .into_iter()
+                .map(|v| match v {
+                    Variable::Fixed(s) | Variable::Free(s) => s,
+                })
+                .collect()
+        })
+        .collect();
+    rows.sort();
+    for row in &rows {
+        let mut object = Map::new();
+        for (column, s) in decl.columns.iter().zip(row) {
+            object.insert(column.name.clone(), value(s, Some(column.kind)));
+        }
+        writeln!(output, "{}", Value::Object(object)).map_err(|e| Error::io(relation, e))?;
+    }
+    Ok(rows.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Statement;
+    use crate::engine::RustEngine;
+    use crate::parser;
+
+    fn engine(program: &str) -> RustEngine {
+        let mut e = RustEngine::new();
+        for s in parser::statements(program).unwrap().1 {
+            match s {
+                Statement::Fact(f) => {
+                    e.push_fact(f).unwrap();
+                }
+                Statement::Rule(r) => e.push_rule(r).unwrap(),
+                Statement::Declaration(d) => e.declare(d).unwrap(),
+                x => panic!("not in test programs {:?}", x),
+            }
+        }
+        e
+    }
+
+    fn ask(e: &RustEngine, q: &str) -> String {
+        let q = match parser::statement(q) {
+            Ok((_, Statement::Query(q))) => q,
+            x => panic!("not a query {:?}", x),
+        };
+        let answers = e.query(q.clone()).unwrap().unwrap_or_default();
+        bindings(e, &q, &answers, &[]).to_string()
+    }
+
+    #[test]
+    fn test_import_arrays_and_objects() {
+        let mut e = engine(".decl person(name: symbol, age: number)");
+        let file = r#"
+            {"age": 30, "name": "alice", "email": "ignored"}
+            ["bob", "041"]
+        "#;
+        assert_eq!(Ok(2), import_from(&mut e, "person", file.as_bytes(), "people.jsonl"));
+        assert_eq!(r#"[{"N":"bob"}]"#, ask(&e, "person(N, 41)?"));
+    }
+
+    #[test]
+    fn test_import_reports_the_bad_line() {
+        let mut e = engine(".decl person(name: symbol, age: number)");
+        let file = "[\"alice\", 30]\n{\"name\": \"bob\"}\n";
+        assert_eq!(
+            Err(Error::Schema(r#"people.jsonl:2: no age in {"name": "bob"}"#.to_string())),
+            import_from(&mut e, "person", file.as_bytes(), "people.jsonl")
+        );
+        assert!(import_from(&mut e, "person", "[\"carol\", 1.5]".as_bytes(), "x").is_err());
+    }
+
+    #[test]
+    fn test_answers_as_bindings() {
+        let e = engine(r#"
+            .decl age(name: symbol, years: number)
+            age("Alice Smith", 30). age(bob, 41).
+            edge(a, b).
+        "#);
+        assert_eq!(r#"[{"N":"Alice Smith"}]"#, ask(&e, "age(N, 30)?"));
+        assert_eq!(r#"[{"Y":41}]"#, ask(&e, "age(bob, Y)?"));
+        assert_eq!(r#"[{}]"#, ask(&e, "edge(a, b)?"));
+        assert_eq!(r#"[]"#, ask(&e, "edge(b, a)?"));
+    }
+
+    #[test]
+    fn test_export_lines() {
+        let e = engine(".decl age(name: symbol, years: number) age(bob, 41). age(al, 7).");
+        let mut out = vec![];
+        assert_eq!(Ok(2), export_to(&e, "age", &mut out));
+        assert_eq!(
+            "{\"name\":\"al\",\"years\":7}\n{\"name\":\"bob\",\"years\":41}\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
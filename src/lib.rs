@@ -1,5 +1,84 @@
-extern crate nom;
+//! An embeddable datalog engine.
+//!
+//! Load a program, add facts as they come in, and ask queries:
+//!
+//! ```
+//! use datalog::Engine;
+//!
+//! let mut engine = Engine::builder().threads(2).build();
+//! engine.load_program("
+//!     parent(alice, bob). parent(bob, carol).
+//!     ancestor(X, Y) :- parent(X, Y).
+//!     ancestor(X, Y) :- parent(X, Z), ancestor(Z, Y).
+//! ")?;
+//! for answer in engine.query("ancestor(alice, Who)")? {
+//!     println!("{}", &answer["Who"]);
+//! }
+//! # Ok::<(), datalog::Error>(())
+//! ```
+// so `#[derive(Relation)]` inside this crate can name it like everyone else
+extern crate self as datalog;
 
-mod ast;
+mod api;
+mod delimited;
+#[cfg(test)]
+mod differential;
+mod directives;
 mod engine;
-mod parser;
+mod intern;
+mod json;
+mod magic;
+mod modules;
+mod planner;
+mod pretty;
+mod relation;
+mod snapshot;
+mod sql;
+mod sqlite;
+mod strata;
+mod topdown;
+
+// the parser, ast and Error live in their own crate so datalog-derive can
+// share them, everything here still names them crate::ast and so on
+use datalog_syntax::{ast, error, parser, safety};
+
+pub use crate::api::{Answer, Answers, Engine, EngineBuilder, Program, Relation};
+#[doc(hidden)]
+pub use crate::api::parse_column;
+pub use crate::ast::ColumnType;
+pub use crate::error::Error;
+pub use datalog_derive::{datalog, Relation};
+
+/// Not part of the API, the engines underneath [`Engine`] for `benches/` to
+/// compare and what the `datalog` command line needs from the rest of the
+/// crate. Anything in here can change without notice.
+#[doc(hidden)]
+pub mod internals {
+    pub use crate::ast::{Fact, Rule, Statement, Variable};
+    pub use crate::engine::{DatalogEngine, RustEngine};
+    pub use crate::parser::{program, query};
+    pub use crate::sql::SqliteEngine;
+    pub use crate::topdown::TopDownEngine;
+
+    pub mod directives {
+        pub use crate::directives::{input, output};
+    }
+    pub mod json {
+        pub use crate::json::bindings;
+    }
+    pub mod modules {
+        pub use crate::modules::{include, locate, resolve};
+    }
+    pub mod parser {
+        pub use crate::parser::{parse_error, program, query, statements};
+    }
+    pub mod pretty {
+        pub use crate::pretty::program;
+    }
+    pub mod snapshot {
+        pub use crate::snapshot::{load, save};
+    }
+    pub mod sql {
+        pub use crate::sql::compile;
+    }
+}
@@ -0,0 +1,204 @@
+#![allow(unused_imports, dead_code)]
+
+use std::collections::HashMap;
+
+/*
+ * magic sets: rewrites the rules behind a query so that evaluating them bottom
+ * up only derives tuples the query can actually use
+ */
+use crate::engine::{Atom, CompiledRule, Goal, RelKey, Term};
+use crate::intern::Sym;
+use crate::planner;
+
+/// which arguments of a derived relation are known (bound) when it gets asked for
+type Adornment = Vec<bool>;
+
+/// The rewritten rules for one query. Evaluate `rules` starting from a
+/// `seed` relation holding one tuple and the answers end up in `answer`.
+#[derive(Debug)]
+pub struct MagicProgram {
+    pub rules: Vec<CompiledRule>,
+    pub seed: (RelKey, Vec<Sym>),
+    pub answer: RelKey,
+}
+
+struct Rewriter<'a> {
+    rules: &'a [CompiledRule],
+    // `path` asked for with its first argument bound becomes `path_bf`, and
+    // the bindings it gets asked with are collected in `magic_path_bf`
+    adorned: HashMap<(RelKey, Adornment), RelKey>,
+    magic: HashMap<(RelKey, Adornment), RelKey>,
+    next_sym: usize,
+    todo: Vec<(RelKey, Adornment)>,
+    out: Vec<CompiledRule>,
+}
+
+/// Rewrites `rules` for `query`, binding the query's constants.
+///
+/// Every derived relation `p` gets a copy `p_a` per adornment `a` it can be
+/// asked with, and a magic relation holding the values of its bound arguments
+/// that are actually needed. Each rule for `p_a` is guarded by
+/// `magic_p_a`, and every derived atom `q` in a rule body adds a rule that
+/// passes the bindings known at that point down into `magic_q_b`. Bindings
+/// flow through the body left to right as written, the same way the rule reads.
+///
+/// The invented relations get scratch symbols starting at `next_sym`, which
+/// must be past everything in the engine's symbol table.
+pub fn rewrite(rules: &[CompiledRule], query: &Atom, next_sym: usize) -> MagicProgram {
+    let mut r = Rewriter {
+        rules,
+        adorned: HashMap::new(),
+        magic: HashMap::new(),
+        next_sym,
+        todo: vec![],
+        out: vec![],
+    };
+    let adornment: Adornment = query
+        .terms
+        .iter()
+        .map(|t| match t {
+            Term::Const(_) => true,
+            Term::Var(_) => false,
+        })
+        .collect();
+    let answer = r.adorned(query.relation, &adornment);
+    let seed_relation = r.magic(query.relation, &adornment);
+    let seed = query
+        .terms
+        .iter()
+        .filter_map(|t| match t {
+            Term::Const(s) => Some(*s),
+            Term::Var(_) => None,
+        })
+        .collect();
+
+    while let Some((relation, adornment)) = r.todo.pop() {
+        r.rewrite_relation(relation, &adornment);
+    }
+    MagicProgram {
+        rules: r.out,
+        seed: (seed_relation, seed),
+        answer,
+    }
+}
+
+fn bound_terms(terms: &[Term], adornment: &[bool]) -> Vec<Term> {
+    terms
+        .iter()
+        .zip(adornment)
+        .filter(|(_, b)| **b)
+        .map(|(t, _)| *t)
+        .collect()
+}
+
+impl<'a> Rewriter<'a> {
+    fn fresh(&mut self, arity: usize) -> RelKey {
+        self.next_sym += 1;
+        (Sym::scratch(self.next_sym - 1), arity)
+    }
+
+    fn is_derived(&self, relation: RelKey) -> bool {
+        self.rules.iter().any(|r| r.head.relation == relation)
+    }
+
+    fn adorned(&mut self, relation: RelKey, adornment: &[bool]) -> RelKey {
+        let key = (relation, adornment.to_vec());
+        if let Some(adorned) = self.adorned.get(&key) {
+            return *adorned;
+        }
+        let adorned = self.fresh(relation.1);
+        self.adorned.insert(key, adorned);
+        self.todo.push((relation, adornment.to_vec()));
+        adorned
+    }
+
+    fn magic(&mut self, relation: RelKey, adornment: &[bool]) -> RelKey {
+        let key = (relation, adornment.to_vec());
+        if let Some(magic) = self.magic.get(&key) {
+            return *magic;
+        }
+        let arity = adornment.iter().filter(|b| **b).count();
+        let magic = self.fresh(arity);
+        self.magic.insert(key, magic);
+        magic
+    }
+
+    fn rewrite_relation(&mut self, relation: RelKey, adornment: &[bool]) {
+        let adorned = self.adorned(relation, adornment);
+        let magic = self.magic(relation, adornment);
+
+        // facts stored under a derived relation's name still count, as long as they're asked for
+        let columns: Vec<Term> = (0..relation.1).map(Term::Var).collect();
+        self.out.push(CompiledRule {
+            head: Atom {
+                relation: adorned,
+                terms: columns.clone(),
+            },
+            body: vec![
+                Goal::Atom(Atom {
+                    relation: magic,
+                    terms: bound_terms(&columns, adornment),
+                }),
+                Goal::Atom(Atom {
+                    relation,
+                    terms: columns,
+                }),
+            ],
+            var_count: relation.1,
+        });
+
+        let rules: Vec<&CompiledRule> = self
+            .rules
+            .iter()
+            .filter(|r| r.head.relation == relation)
+            .collect();
+        for rule in rules {
+            let mut bound = vec![false; rule.var_count];
+            for t in bound_terms(&rule.head.terms, adornment) {
+                if let Term::Var(i) = t {
+                    bound[i] = true;
+                }
+            }
+            let mut body = vec![Goal::Atom(Atom {
+                relation: magic,
+                terms: bound_terms(&rule.head.terms, adornment),
+            })];
+            for goal in &rule.body {
+                match goal {
+                    Goal::Atom(atom) if self.is_derived(atom.relation) => {
+                        let wanted: Adornment = atom
+                            .terms
+                            .iter()
+                            .map(|t| planner::is_bound(*t, &bound))
+                            .collect();
+                        // whatever the body knows by now is what gets asked of `atom`
+                        let asked = self.magic(atom.relation, &wanted);
+                        self.out.push(CompiledRule {
+                            head: Atom {
+                                relation: asked,
+                                terms: bound_terms(&atom.terms, &wanted),
+                            },
+                            body: body.clone(),
+                            var_count: rule.var_count,
+                        });
+                        let answered = self.adorned(atom.relation, &wanted);
+                        body.push(Goal::Atom(Atom {
+                            relation: answered,
+                            terms: atom.terms.clone(),
+                        }));
+                    }
+                    _ => body.push(goal.clone()),
+                }
+                planner::bind(goal, &mut bound);
+            }
+            self.out.push(CompiledRule {
+                head: Atom {
+                    relation: adorned,
+                    terms: rule.head.terms.clone(),
+                },
+                body,
+                var_count: rule.var_count,
+            });
+        }
+    }
+}
@@ -1,13 +1,349 @@
 #![allow(unused_imports,dead_code)]
 
+mod transcript;
+
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
+use std::io::Read;
+use std::path::Path;
+
+use datalog::internals::{directives, json, modules, parser, pretty, snapshot, sql};
+use datalog::internals::{DatalogEngine, Fact, RustEngine, Statement};
+use datalog::Error;
+
+// how query answers get printed
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    // edge(a, b).
+    Text,
+    // [{"X":"a","Y":"b"}]
+    Json,
+}
+
+fn format(name: &str) -> Option<Format> {
+    match name {
+        "text" => Some(Format::Text),
+        "json" => Some(Format::Json),
+        _ => None,
+    }
+}
+
+// runs every statement in `text` and returns what the REPL should print.
+// files named by .input and .output are looked for relative to `dir`.
+// `file` is where `text` was read from, when it's the whole of a file
+fn eval(engine: &mut RustEngine, dir: &Path, file: Option<&str>, format: Format, text: &str) -> Vec<String> {
+    let mut out = vec![];
+    let (rest, mut statements) = match parser::statements(text) {
+        Ok(parsed) => parsed,
+        Err(_) => (text, vec![]),
+    };
+    if let Some(file) = file {
+        modules::locate(&mut statements, file);
+    }
+    let statements = match modules::resolve(statements, dir, &|name| engine.defines(name)) {
+        Ok(statements) => statements,
+        Err(e) => {
+            out.push(format!("Error: {}", e));
+            vec![]
+        }
+    };
+    // everything up to the next query goes in as one batch, so constraints
+    // get checked once for all of it but queries never see it unchecked
+    let asks = |s: &Statement| matches!(s, Statement::Query(_) | Statement::AsOf(_) | Statement::Output(_));
+    let mut statements = statements.into_iter().peekable();
+    while statements.peek().is_some() {
+        let checked = engine.batch(|engine| {
+            while let Some(statement) = statements.next_if(|s| !asks(s)) {
+                run(engine, dir, format, statement, &mut out);
+            }
+        });
+        if let Err(e) = checked {
+            out.push(format!("Error: {}", e));
+        }
+        if let Some(statement) = statements.next() {
+            run(engine, dir, format, statement, &mut out);
+        }
+    }
+    if !rest.trim().is_empty() {
+        out.push(format!("Error: {}", parser::parse_error(text, rest)));
+    }
+    out
+}
+
+// runs one statement, adding what it prints to `out`
+fn run(engine: &mut RustEngine, dir: &Path, format: Format, statement: Statement, out: &mut Vec<String>) {
+    let result = match statement {
+        Statement::Fact(f) => engine.push_fact(f).map(|new| {
+            if !new {
+                out.push("already known.".to_string());
+            }
+        }),
+        Statement::Rule(r) => engine.push_rule(r),
+        Statement::Retract(f) => engine.retract_fact(f).map(|gone| {
+            if !gone {
+                out.push("not known.".to_string());
+            }
+        }),
+        Statement::Constraint(c) => engine.push_constraint(c),
+        // undefined answers only come up with --well-founded
+        Statement::Query(q) => engine.query_with_undefined(q.clone()).map(|answers| {
+            let (answers, undefined) = answers.unwrap_or_default();
+            match format {
+                Format::Text => {
+                    out.extend(answers.iter().map(|a| format!("{}.", a)));
+                    out.extend(undefined.iter().map(|a| format!("{}. % undefined", a)));
+                }
+                Format::Json => out.push(json::bindings(engine, &q, &answers, &undefined).to_string()),
+            }
+        }),
+        Statement::AsOf(a) => engine.as_of(a.version).and_then(|then| {
+            let answers = then.query(a.query.clone())?.unwrap_or_default();
+            match format {
+                Format::Text => out.extend(answers.iter().map(|a| format!("{}.", a))),
+                Format::Json => out.push(json::bindings(&then, &a.query, &answers, &[]).to_string()),
+            }
+            Ok(())
+        }),
+        Statement::Declaration(d) => engine.declare(d),
+        Statement::Input(d) => directives::input(engine, dir, &d)
+            .map(|n| out.push(format!("loaded {} facts into {}.", n, d.relation))),
+        Statement::Output(d) => directives::output(engine, dir, &d)
+            .map(|(n, path)| out.push(format!("wrote {} facts to {}.", n, path.display()))),
+        Statement::Include(_) | Statement::Module(_) => unreachable!("modules::resolve leaves none behind"),
+    };
+    if let Err(e) = result {
+        out.push(format!("Error: {}", e));
+    }
+}
+
+// the answers :diff found, + for the ones that turned up and - for the ones that went away
+fn diff(engine: &RustEngine, args: &str) -> Result<Vec<String>, String> {
+    let usage = || "use :diff with a query and two versions, like :diff path(a, X) 3 7".to_string();
+    let mut words = args.trim().rsplitn(3, char::is_whitespace);
+    let to = words.next().and_then(|v| v.parse().ok()).ok_or_else(usage)?;
+    let from = words.next().and_then(|v| v.parse().ok()).ok_or_else(usage)?;
+    let query = parser::query(words.next().ok_or_else(usage)?).map_err(|e| e.to_string())?;
+    let (added, removed) = engine.diff(query, from, to).map_err(|e| e.to_string())?;
+    let added = added.iter().map(|a| format!("+{}.", a));
+    Ok(added.chain(removed.iter().map(|r| format!("-{}.", r))).collect())
+}
+
+fn usage() -> ! {
+    eprintln!("usage: datalog [--threads N] [--format text|json] [--well-founded] [--dump-ast] [file.dl]");
+    eprintln!("       datalog fmt [--check] [file.dl ...]");
+    eprintln!("       datalog sql [file.dl]");
+    eprintln!("       datalog test file.dl ...");
+    std::process::exit(2);
+}
+
+// datalog --dump-ast: the parsed program as a json array of statements, for
+// tools that would rather not parse datalog themselves
+#[cfg(feature = "serde")]
+fn dump_ast(text: &str) -> Result<String, String> {
+    let statements = parser::program(text).map_err(|e| e.to_string())?;
+    serde_json::to_string_pretty(&statements).map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "serde"))]
+fn dump_ast(_: &str) -> Result<String, String> {
+    Err("--dump-ast needs datalog built with the serde feature".to_string())
+}
+
+// datalog fmt: rewrites each file in its canonical layout, or stdin to stdout
+// when there are no files. --check only says which files would change
+fn fmt(args: Vec<String>) -> ! {
+    let check = args.iter().any(|a| a == "--check");
+    let files: Vec<&String> = args.iter().filter(|a| *a != "--check").collect();
+    if files.iter().any(|f| f.starts_with('-')) {
+        usage();
+    }
+    if files.is_empty() {
+        let mut text = String::new();
+        if let Err(e) = std::io::stdin().read_to_string(&mut text) {
+            eprintln!("stdin: {}", e);
+            std::process::exit(1);
+        }
+        match pretty::program(&text) {
+            Ok(tidy) if check && tidy != text => std::process::exit(1),
+            Ok(_) if check => std::process::exit(0),
+            Ok(tidy) => print!("{}", tidy),
+            Err(e) => {
+                eprintln!("stdin: {}", e);
+                std::process::exit(1);
+            }
+        }
+        std::process::exit(0);
+    }
+    let mut failed = false;
+    for file in files {
+        let result = std::fs::read_to_string(file)
+            .map_err(|e| e.to_string())
+            .and_then(|text| match pretty::program(&text) {
+                Ok(tidy) => Ok((text, tidy)),
+                Err(e) => Err(e.to_string()),
+            });
+        match result {
+            Ok((text, tidy)) if text == tidy => {}
+            Ok(_) if check => {
+                println!("{} isn't formatted", file);
+                failed = true;
+            }
+            Ok((_, tidy)) => {
+                if let Err(e) = std::fs::write(file, tidy) {
+                    eprintln!("{}: {}", file, e);
+                    failed = true;
+                }
+            }
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                failed = true;
+            }
+        }
+    }
+    std::process::exit(if failed { 1 } else { 0 });
+}
 
-fn eval<T>(i: T) -> T {
-    i
+// datalog sql: the program as a sqlite script, so its rules can run inside
+// an existing database. reads stdin when there's no file
+fn sql(args: Vec<String>) -> ! {
+    let (name, text) = match args.as_slice() {
+        [] => {
+            let mut text = String::new();
+            ("stdin".to_string(), std::io::stdin().read_to_string(&mut text).map(|_| text))
+        }
+        [file] if !file.starts_with('-') => (file.clone(), std::fs::read_to_string(file)),
+        _ => usage(),
+    };
+    let dir = Path::new(&name).parent().unwrap_or_else(|| Path::new(""));
+    let script = text.map_err(|e| e.to_string()).and_then(|text| {
+        parser::program(&text)
+            .and_then(|p| modules::include(p, dir))
+            .and_then(|p| sql::compile(&p))
+            .map_err(|e| e.to_string())
+    });
+    match script {
+        Ok(script) => print!("{}", script),
+        Err(e) => {
+            eprintln!("{}: {}", name, e);
+            std::process::exit(1);
+        }
+    }
+    std::process::exit(0);
+}
+
+// datalog test: runs repl transcripts, see src/transcript.rs, each in a
+// fresh engine. fails when any step printed something unexpected
+fn test(files: Vec<String>) -> ! {
+    if files.is_empty() || files.iter().any(|f| f.starts_with('-')) {
+        usage();
+    }
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+    for file in &files {
+        let text = match std::fs::read_to_string(file) {
+            Ok(text) => text,
+            Err(e) => {
+                println!("{}: {}", file, e);
+                failed += 1;
+                continue;
+            }
+        };
+        if let Some(reason) = transcript::skipped(&text) {
+            println!("{}: skipped, {}", file, reason);
+            skipped += 1;
+            continue;
+        }
+        let mut engine = RustEngine::new();
+        let dir = Path::new(file).parent().unwrap_or_else(|| Path::new(""));
+        let failures = transcript::check(&transcript::steps(&text), |input| {
+            eval(&mut engine, dir, None, Format::Text, input)
+        });
+        if failures.is_empty() {
+            println!("{}: ok", file);
+            passed += 1;
+        } else {
+            for failure in failures {
+                println!("{} {}", file, failure);
+            }
+            failed += 1;
+        }
+    }
+    println!("{} passed, {} failed, {} skipped", passed, failed, skipped);
+    std::process::exit(if failed > 0 { 1 } else { 0 });
 }
 
 fn main() {
+    let mut engine = RustEngine::new();
+    let mut threads = 0;
+    let mut file = None;
+    let mut answers = Format::Text;
+    let mut ast = false;
+    let mut well_founded = false;
+    if std::env::args().nth(1) == Some("fmt".to_string()) {
+        fmt(std::env::args().skip(2).collect());
+    }
+    if std::env::args().nth(1) == Some("sql".to_string()) {
+        sql(std::env::args().skip(2).collect());
+    }
+    if std::env::args().nth(1) == Some("test".to_string()) {
+        test(std::env::args().skip(2).collect());
+    }
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--threads" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) => threads = n,
+                None => usage(),
+            },
+            "--format" => match args.next().as_ref().and_then(|f| format(f)) {
+                Some(f) => answers = f,
+                None => usage(),
+            },
+            "--dump-ast" => ast = true,
+            "--well-founded" => well_founded = true,
+            _ if file.is_none() && !arg.starts_with('-') => file = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    engine.set_threads(threads);
+    engine.set_well_founded(well_founded);
+
+    if ast {
+        let text = match &file {
+            Some(file) => std::fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e)),
+            None => {
+                let mut text = String::new();
+                std::io::stdin().read_to_string(&mut text).map(|_| text).map_err(|e| e.to_string())
+            }
+        };
+        match text.and_then(|text| dump_ast(&text)) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        std::process::exit(0);
+    }
+
+    // with a program file, run it and quit
+    if let Some(file) = file {
+        let text = match std::fs::read_to_string(&file) {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                std::process::exit(1);
+            }
+        };
+        let dir = Path::new(&file).parent().unwrap_or_else(|| Path::new(""));
+        let mut failed = false;
+        for result in eval(&mut engine, dir, Some(&file), answers, &text) {
+            failed |= result.starts_with("Error:");
+            println!("{}", result);
+        }
+        std::process::exit(if failed { 1 } else { 0 });
+    }
+
     // `()` can be used when no completer is required
     let mut rl = Editor::<()>::new();
     /*
@@ -20,8 +356,67 @@ fn main() {
         match readline {
             Ok(line) => {
                 //rl.add_history_entry(line.as_str());
-                let result = eval(line);
-                println!("Line: {}", result);
+                // :format json switches how answers get printed from here on
+                if let Some(name) = line.trim().strip_prefix(":format") {
+                    match format(name.trim()) {
+                        Some(f) => answers = f,
+                        None => println!("Error: formats are text and json"),
+                    }
+                    continue;
+                }
+                if let Some(file) = line.trim().strip_prefix(":save") {
+                    match snapshot::save(&engine, Path::new(file.trim())) {
+                        Ok(()) => println!("saved to {}.", file.trim()),
+                        Err(e) => println!("Error: {}", e),
+                    }
+                    continue;
+                }
+                if line.trim() == ":version" {
+                    println!("version {}.", engine.version());
+                    continue;
+                }
+                // :diff path(a, X) 3 7 shows how the answers changed from version 3 to 7
+                if let Some(args) = line.trim().strip_prefix(":diff") {
+                    match diff(&engine, args) {
+                        Ok(lines) => lines.iter().for_each(|l| println!("{}", l)),
+                        Err(e) => println!("Error: {}", e),
+                    }
+                    continue;
+                }
+                // :begin, :commit and :rollback, see DatalogEngine::begin
+                let transaction = match line.trim() {
+                    ":begin" => Some(engine.begin().map(|_| "begun.").map_err(|e| e.to_string())),
+                    ":commit" => Some(engine.commit().map(|_| "committed.").map_err(|e| match e {
+                        Error::Constraint(_) => format!("{}, rolled back", e),
+                        e => e.to_string(),
+                    })),
+                    ":rollback" => Some(engine.rollback().map(|_| "rolled back.").map_err(|e| e.to_string())),
+                    _ => None,
+                };
+                if let Some(result) = transaction {
+                    match result {
+                        Ok(done) => println!("{}", done),
+                        Err(e) => println!("Error: {}", e),
+                    }
+                    continue;
+                }
+                // :load replaces everything, a snapshot that fails to load leaves things as they were
+                if let Some(file) = line.trim().strip_prefix(":load") {
+                    let mut loaded = RustEngine::new();
+                    loaded.set_threads(threads);
+                    loaded.set_well_founded(well_founded);
+                    match snapshot::load(&mut loaded, Path::new(file.trim())) {
+                        Ok(_) => {
+                            engine = loaded;
+                            println!("loaded {}.", file.trim());
+                        }
+                        Err(e) => println!("Error: {}", e),
+                    }
+                    continue;
+                }
+                for result in eval(&mut engine, Path::new(""), None, answers, &line) {
+                    println!("{}", result);
+                }
             }
             Err(ReadlineError::Interrupted) => {
                 println!("CTRL-C");
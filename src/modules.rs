@@ -0,0 +1,280 @@
+#![allow(unused_imports, dead_code)]
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/*
+ * .include and .module, so a big program can be split across files and teams
+ * without fighting over relation names:
+ *
+ *   .include "graph.dl"              the statements in graph.dl, found
+ *                                    relative to the file including it
+ *   .module graph {                  the relations made in here are
+ *       node(X) :- edge(X, _).       graph::node and graph::path, nothing
+ *       path(X, Y) :- edge(X, Y).    to do with a node made anywhere else
+ *   }
+ *   graph::path(a, X)?
+ *
+ * a name used inside a module means the module's own relation when there is
+ * one by that name, otherwise whatever it means one module out, and so on up
+ * to the top where names mean themselves. `edge` above is the top level edge.
+ * a name with :: in it is looked up the same way, so a module can say
+ * sub::rel for a relation in a module of its own.
+ *
+ * the engines never see any of this, they get the statements flattened with
+ * every relation named in full
+ */
+use crate::ast::{BodyExpression, Fact, Module, Rule, Statement};
+use crate::error::Error;
+use crate::parser;
+
+/// [`include`] then [`flatten`], what a program goes through before its
+/// statements are run.
+pub fn resolve(statements: Vec<Statement>, dir: &Path, known: &dyn Fn(&str) -> bool) -> Result<Vec<Statement>, Error> {
+    Ok(flatten(include(statements, dir)?, known))
+}
+
+/// Swaps every `.include`, inside modules too, for the statements of the
+/// file it names. Paths are taken relative to `dir`, and the ones in an
+/// included file relative to that file's directory. A file that ends up
+/// including itself is an error naming the files going round.
+pub fn include(statements: Vec<Statement>, dir: &Path) -> Result<Vec<Statement>, Error> {
+    expand(statements, dir, &mut vec![])
+}
+
+/// Says the constraints in `statements`, inside modules too, were read from
+/// `file`, so a broken one can be found. Ones that already name a file, like
+/// those of an included file, keep it.
+pub fn locate(statements: &mut [Statement], file: &str) {
+    for statement in statements {
+        match statement {
+            Statement::Constraint(c) if c.file.is_none() => c.file = Some(file.to_string()),
+            Statement::Module(m) => locate(&mut m.statements, file),
+            _ => {}
+        }
+    }
+}
+
+// `including` is every file being read on the way down to here, as found on
+// disk and as it was named
+fn expand(
+    statements: Vec<Statement>,
+    dir: &Path,
+    including: &mut Vec<(PathBuf, String)>,
+) -> Result<Vec<Statement>, Error> {
+    let mut out = vec![];
+    for statement in statements {
+        match statement {
+            Statement::Include(file) => {
+                let path = dir.join(&file);
+                let shown = path.display().to_string();
+                let found = fs::canonicalize(&path).map_err(|e| Error::io(&shown, e))?;
+                if let Some(start) = including.iter().position(|(p, _)| *p == found) {
+                    let mut cycle: Vec<&str> = including[start..].iter().map(|(_, s)| s.as_str()).collect();
+                    cycle.push(&shown);
+                    return Err(Error::Schema(format!("include cycle: {}", cycle.join(" -> "))));
+                }
+                let text = fs::read_to_string(&path).map_err(|e| Error::io(&shown, e))?;
+                let mut parsed = parser::program(&text).map_err(|e| Error::io(&shown, e))?;
+                locate(&mut parsed, &shown);
+                including.push((found, shown));
+                let expanded = expand(parsed, path.parent().unwrap_or_else(|| Path::new("")), including);
+                including.pop();
+                out.extend(expanded?);
+            }
+            Statement::Module(m) => out.push(Statement::Module(Module {
+                name: m.name,
+                statements: expand(m.statements, dir, including)?,
+            })),
+            s => out.push(s),
+        }
+    }
+    Ok(out)
+}
+
+/// Takes the `.module` blocks out of `statements`, leaving every relation
+/// named in full. `known` says whether a relation was already made before
+/// these statements, like `RustEngine::defines`, so a module can be added
+/// to in a later program.
+///
+/// `.include`s are left where they are, see [`include`].
+pub fn flatten(statements: Vec<Statement>, known: &dyn Fn(&str) -> bool) -> Vec<Statement> {
+    let mut made = HashSet::new();
+    made_in(&statements, "", &mut made);
+    let mut out = vec![];
+    rename(statements, &mut vec![String::new()], &|name: &str| made.contains(name) || known(name), &mut out);
+    out
+}
+
+fn qualified(module: &str, name: &str) -> String {
+    if module.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}::{}", module, name)
+    }
+}
+
+// every relation a fact, rule, .decl or .input in `module` makes, in full
+fn made_in(statements: &[Statement], module: &str, made: &mut HashSet<String>) {
+    for statement in statements {
+        match statement {
+            Statement::Fact(f) | Statement::Rule(Rule { head: f, .. }) => {
+                made.insert(qualified(module, &f.name));
+            }
+            Statement::Declaration(d) => {
+                made.insert(qualified(module, &d.name));
+            }
+            Statement::Input(d) => {
+                made.insert(qualified(module, &d.relation));
+            }
+            Statement::Module(m) => made_in(&m.statements, &qualified(module, &m.name), made),
+            _ => {}
+        }
+    }
+}
+
+// `modules` are the ones `statements` are inside of, outermost (the top
+// level, "") first
+fn rename(
+    statements: Vec<Statement>,
+    modules: &mut Vec<String>,
+    made: &dyn Fn(&str) -> bool,
+    out: &mut Vec<Statement>,
+) {
+    let here = modules.last().cloned().unwrap_or_default();
+    for mut statement in statements {
+        match &mut statement {
+            Statement::Fact(f) => f.name = qualified(&here, &f.name),
+            Statement::Rule(r) => {
+                r.head.name = qualified(&here, &r.head.name);
+                used_in(&mut r.body, modules, made);
+            }
+            Statement::Declaration(d) => d.name = qualified(&here, &d.name),
+            Statement::Input(d) => d.relation = qualified(&here, &d.relation),
+            Statement::Constraint(c) => used_in(&mut c.body, modules, made),
+            Statement::Retract(f) | Statement::Query(f) => used(&mut f.name, modules, made),
+            Statement::AsOf(a) => used(&mut a.query.name, modules, made),
+            Statement::Output(d) => used(&mut d.relation, modules, made),
+            Statement::Include(_) => {}
+            Statement::Module(m) => {
+                modules.push(qualified(&here, &m.name));
+                rename(std::mem::take(&mut m.statements), modules, made, out);
+                modules.pop();
+                continue;
+            }
+        }
+        out.push(statement);
+    }
+}
+
+// names the innermost module with a relation by that name, or the top level
+fn used(name: &mut String, modules: &[String], made: &dyn Fn(&str) -> bool) {
+    if let Some(module) = modules.iter().rev().find(|m| m.is_empty() || made(&qualified(m, name))) {
+        *name = qualified(module, name);
+    }
+}
+
+fn used_in(body: &mut [BodyExpression], modules: &[String], made: &dyn Fn(&str) -> bool) {
+    for expression in body {
+        match expression {
+            BodyExpression::Fact(f) | BodyExpression::Not(f) => used(&mut f.name, modules, made),
+            BodyExpression::Count(c) => used(&mut c.over.name, modules, made),
+            BodyExpression::Equals(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flattened(program: &str, known: &[&str]) -> String {
+        let statements = parser::program(program).unwrap();
+        let flat = flatten(statements, &|name| known.contains(&name));
+        flat.iter().map(|s| s.to_string()).collect::<Vec<_>>().join("\n")
+    }
+
+    #[test]
+    fn test_names_resolve_from_the_inside_out() {
+        let program = r#"
+            node(top).
+            .module graph {
+                node(X) :- edge(X, _).
+                .module walk {
+                    step(X, Y) :- edge(X, Y), node(X), !graph::node(Y).
+                }
+                far(X) :- walk::step(X, _), sink(X).
+                .output far
+            }
+            graph::far(X)?
+            graph::walk::step(a, X)?
+        "#;
+        assert_eq!(
+            "node(top).
+graph::node(X) :- edge(X, _).
+graph::walk::step(X, Y) :- edge(X, Y), graph::node(X), !graph::node(Y).
+graph::far(X) :- graph::walk::step(X, _), sink(X).
+.output graph::far
+graph::far(X)?
+graph::walk::step(a, X)?",
+            flattened(program, &[])
+        );
+        // what's already loaded counts as made too
+        assert_eq!(
+            "graph::far(X) :- graph::sink(X), node(X).",
+            flattened(".module graph { far(X) :- sink(X), node(X). }", &["graph::sink"])
+        );
+    }
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("datalog-{}-{}", name, std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = scratch("include");
+        fs::write(dir.join("lib").join("graph.dl"), ".include \"edges.dl\"\npath(X, Y) :- edge(X, Y).\n").unwrap();
+        fs::write(dir.join("lib").join("edges.dl"), "edge(a, b).\n").unwrap();
+        let statements = parser::program(".module g { .include \"lib/graph.dl\" }").unwrap();
+        let resolved = resolve(statements, &dir, &|_| false).unwrap();
+        let shown: Vec<String> = resolved.iter().map(|s| s.to_string()).collect();
+        assert_eq!(vec!["g::edge(a, b).", "g::path(X, Y) :- g::edge(X, Y)."], shown);
+
+        // constraints remember the file they came from
+        fs::write(dir.join("lib").join("edges.dl"), "edge(a, b).\n:- edge(X, X).\n").unwrap();
+        let statements = parser::program(".include \"lib/graph.dl\"").unwrap();
+        let edges = dir.join("lib").join("edges.dl").display().to_string();
+        match &include(statements, &dir).unwrap()[1] {
+            Statement::Constraint(c) => assert_eq!((Some(edges), 2), (c.file.clone(), c.line)),
+            s => panic!("expected a constraint, got {}", s),
+        }
+
+        let missing = parser::program(".include \"nope.dl\"").unwrap();
+        assert!(matches!(include(missing, &dir), Err(Error::Io { .. })));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_include_cycles() {
+        let dir = scratch("cycle");
+        fs::write(dir.join("a.dl"), "edge(a, b).\n.include \"lib/b.dl\"\n").unwrap();
+        fs::write(dir.join("lib").join("b.dl"), ".include \"../a.dl\"\n").unwrap();
+        let statements = parser::program(".include \"a.dl\"").unwrap();
+        let a = dir.join("a.dl").display().to_string();
+        let b = dir.join("lib").join("b.dl").display().to_string();
+        let back = dir.join("lib").join("../a.dl").display().to_string();
+        assert_eq!(
+            Err(Error::Schema(format!("include cycle: {} -> {} -> {}", a, b, back))),
+            include(statements, &dir)
+        );
+        // the same file twice, side by side, isn't a cycle
+        let twice = parser::program(".include \"lib/../a.dl\"").unwrap();
+        fs::write(dir.join("a.dl"), "edge(a, b).\n").unwrap();
+        assert_eq!(1, include(twice.clone(), &dir).unwrap().len());
+        assert_eq!(2, include([twice.clone(), twice].concat(), &dir).unwrap().len());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,192 @@
+#![allow(unused_imports, dead_code)]
+
+/*
+ * decides what order the goals of a rule body get joined in
+ */
+use crate::engine::{Goal, RelKey, Term};
+
+/// how much binding one column of an atom is guessed to shrink its relation by
+const BOUND_COLUMN_SELECTIVITY: f64 = 0.1;
+
+/// Picks the order to evaluate a rule body in, as positions into `body`.
+///
+/// Greedy: at every step take the cheapest goal given the variables bound by
+/// the goals before it, where
+/// - a comparison goes as soon as it can run, since it only ever shrinks the
+///   intermediate result (or binds a variable for free with `X = a`). So does
+///   a negated atom, once every one of its variables is bound
+/// - an atom costs the size of its relation, cut down by
+///   `BOUND_COLUMN_SELECTIVITY` for every column already pinned by a constant
+///   or a bound variable. Atoms that share no variables with what's bound so
+///   far pay for the whole relation, which keeps cross products for last.
+///
+/// Ties keep the order the rule was written in. `cardinality` gets the
+/// position of the atom as well as the relation, since during semi-naive
+/// evaluation the same relation can be read in full at one position and only
+/// the newest tuples at another.
+pub fn plan(
+    body: &[Goal],
+    var_count: usize,
+    cardinality: &dyn Fn(usize, RelKey) -> usize,
+) -> Vec<usize> {
+    let mut bound = vec![false; var_count];
+    let mut remaining: Vec<usize> = (0..body.len()).collect();
+    let mut order = Vec::with_capacity(body.len());
+
+    while !remaining.is_empty() {
+        let mut best: Option<(usize, f64)> = None;
+        for (i, position) in remaining.iter().enumerate() {
+            let cost = match &body[*position] {
+                Goal::Equals { .. } | Goal::Not(_) | Goal::Count { .. } if ready(&body[*position], &bound) => 0.0,
+                Goal::Equals { .. } | Goal::Not(_) | Goal::Count { .. } => continue,
+                Goal::Atom(atom) => {
+                    let pinned = atom.terms.iter().filter(|t| is_bound(**t, &bound)).count();
+                    cardinality(*position, atom.relation) as f64
+                        * BOUND_COLUMN_SELECTIVITY.powi(pinned as i32)
+                }
+            };
+            match best {
+                Some((_, lowest)) if lowest <= cost => {}
+                _ => best = Some((i, cost)),
+            }
+        }
+        // only comparisons, negations and counts on variables nothing binds are left, run them last as written
+        let i = best.map_or(0, |(i, _)| i);
+        let position = remaining.remove(i);
+        bind(&body[position], &mut bound);
+        order.push(position);
+    }
+    order
+}
+
+/// The body in the order it was written, for when the planner is pinned,
+/// except that a comparison, negated atom or count waits until its variables
+/// are bound, or for the end if nothing binds them.
+pub fn written(body: &[Goal], var_count: usize) -> Vec<usize> {
+    let mut bound = vec![false; var_count];
+    let mut remaining: Vec<usize> = (0..body.len()).collect();
+    let mut order = Vec::with_capacity(body.len());
+    while !remaining.is_empty() {
+        let i = remaining
+            .iter()
+            .position(|p| ready(&body[*p], &bound))
+            .unwrap_or(0);
+        let position = remaining.remove(i);
+        bind(&body[position], &mut bound);
+        order.push(position);
+    }
+    order
+}
+
+pub fn is_bound(t: Term, bound: &[bool]) -> bool {
+    match t {
+        Term::Const(_) => true,
+        Term::Var(i) => bound[i],
+    }
+}
+
+// `X = Y` can run once either side is known, `X != Y` and `!edge(X, Y)` need everything,
+// `N = count : { edge(X, Y) }` needs whatever it groups by
+pub fn ready(goal: &Goal, bound: &[bool]) -> bool {
+    match goal {
+        Goal::Equals {
+            equals: true,
+            left,
+            right,
+        } => is_bound(*left, bound) || is_bound(*right, bound),
+        Goal::Equals { left, right, .. } => is_bound(*left, bound) && is_bound(*right, bound),
+        Goal::Not(atom) => atom.terms.iter().all(|t| is_bound(*t, bound)),
+        Goal::Count { grouped, .. } => grouped.iter().all(|i| bound[*i]),
+        Goal::Atom(_) => true,
+    }
+}
+
+/// marks the variables a goal binds once it has run
+pub fn bind(goal: &Goal, bound: &mut [bool]) {
+    let terms = match goal {
+        Goal::Atom(atom) => atom.terms.clone(),
+        Goal::Equals {
+            equals: true,
+            left,
+            right,
+        } if ready(goal, bound) => vec![*left, *right],
+        Goal::Count { result, .. } => vec![*result],
+        Goal::Equals { .. } | Goal::Not(_) => vec![],
+    };
+    for t in terms {
+        if let Term::Var(i) = t {
+            bound[i] = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Atom;
+    use crate::intern::Interner;
+
+    fn atom(i: &mut Interner, name: &str, terms: Vec<Term>) -> Goal {
+        Goal::Atom(Atom {
+            relation: (i.intern(name), terms.len()),
+            terms,
+        })
+    }
+
+    #[test]
+    fn test_small_relation_goes_first() {
+        // big(X, Y), small(Y)
+        let mut i = Interner::new();
+        let body = vec![
+            atom(&mut i, "big", vec![Term::Var(0), Term::Var(1)]),
+            atom(&mut i, "small", vec![Term::Var(1)]),
+        ];
+        let sizes = |_: usize, r: RelKey| if r.1 == 2 { 1000 } else { 10 };
+        assert_eq!(vec![1, 0], plan(&body, 2, &sizes));
+    }
+
+    #[test]
+    fn test_constants_make_atoms_cheap() {
+        // edge(X, Y), edge(a, X)
+        let mut i = Interner::new();
+        let a = i.intern("a");
+        let body = vec![
+            atom(&mut i, "edge", vec![Term::Var(0), Term::Var(1)]),
+            atom(&mut i, "edge", vec![Term::Const(a), Term::Var(0)]),
+        ];
+        assert_eq!(vec![1, 0], plan(&body, 2, &|_, _| 1000));
+    }
+
+    #[test]
+    fn test_comparisons_run_once_bound() {
+        // foo(X), bar(Y), X != b, Y = X
+        let mut i = Interner::new();
+        let b = i.intern("b");
+        let body = vec![
+            atom(&mut i, "foo", vec![Term::Var(0)]),
+            atom(&mut i, "bar", vec![Term::Var(1)]),
+            Goal::Equals {
+                equals: false,
+                left: Term::Var(0),
+                right: Term::Const(b),
+            },
+            Goal::Equals {
+                equals: true,
+                left: Term::Var(1),
+                right: Term::Var(0),
+            },
+        ];
+        // Y = X binds Y, so bar(Y) becomes a lookup instead of a cross product
+        assert_eq!(vec![0, 2, 3, 1], plan(&body, 2, &|_, _| 100));
+    }
+
+    #[test]
+    fn test_ties_keep_written_order() {
+        let mut i = Interner::new();
+        let body = vec![
+            atom(&mut i, "foo", vec![Term::Var(0)]),
+            atom(&mut i, "bar", vec![Term::Var(0)]),
+        ];
+        assert_eq!(vec![0, 1], plan(&body, 1, &|_, _| 5));
+    }
+}
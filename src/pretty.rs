@@ -0,0 +1,193 @@
+#![allow(unused_imports, dead_code)]
+
+/*
+ * the canonical layout of a .dl file, what `datalog fmt` rewrites files into:
+ * one statement per line with the spacing `Display` gives it, rules too long
+ * for a line get one body goal per line, the insides of a .module one indent
+ * in, and comments and paragraph breaks stay where they were
+ */
+use crate::ast::{Rule, Statement};
+use crate::error::Error;
+use crate::parser;
+
+/// rules longer than this get their body split over several lines
+pub const MAX_WIDTH: usize = 80;
+const INDENT: &str = "    ";
+
+/// One statement laid out canonically, without a trailing newline.
+pub fn statement(s: &Statement) -> String {
+    let line = s.to_string();
+    match s {
+        Statement::Rule(r) if line.chars().count() > MAX_WIDTH && r.body.len() > 1 => wrapped(r),
+        Statement::Module(m) => {
+            let inside: Vec<String> = m.statements.iter().map(|s| format!("{}\n", statement(s))).collect();
+            block(&m.name, &inside.concat())
+        }
+        _ => line,
+    }
+}
+
+// .module graph {
+//     node(a).
+// }
+fn block(name: &str, inside: &str) -> String {
+    let mut out = format!(".module {} {{\n", name);
+    for line in inside.lines() {
+        if !line.is_empty() {
+            out.push_str(INDENT);
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out.push('}');
+    out
+}
+
+// path(X, Y) :-
+//     path(X, Z),
+//     edge(Z, Y).
+fn wrapped(r: &Rule) -> String {
+    let body: Vec<String> = r.body.iter().map(|e| format!("{}{}", INDENT, e)).collect();
+    format!("{} :-\n{}.", r.head, body.join(",\n"))
+}
+
+/// Reformats a whole program. Fails without changing anything when part of
+/// it doesn't parse, saying which line that starts on.
+pub fn program(text: &str) -> Result<String, Error> {
+    let mut out = String::new();
+    let mut rest = text;
+    loop {
+        let trimmed = rest.trim_start();
+        let newlines = rest[..rest.len() - trimmed.len()].matches('\n').count();
+        rest = trimmed;
+        if rest.is_empty() {
+            break;
+        }
+        if !out.is_empty() {
+            if newlines == 0 && rest.starts_with('%') {
+                // a comment after a statement stays on its line
+                out.push_str("  ");
+            } else {
+                out.push('\n');
+                if newlines > 1 {
+                    out.push('\n');
+                }
+            }
+        }
+        if rest.starts_with('%') {
+            let end = rest.find('\n').unwrap_or(rest.len());
+            out.push_str(rest[..end].trim_end());
+            rest = &rest[end..];
+            continue;
+        }
+        match parser::statement(rest) {
+            Ok((next, Statement::Module(m))) => {
+                // the inside is laid out like a program of its own, comments and all
+                let written = &rest[..rest.len() - next.len()];
+                let inside = &written[written.find('{').unwrap() + 1..written.rfind('}').unwrap()];
+                out.push_str(&block(&m.name, &program(inside)?));
+                rest = next;
+            }
+            Ok((next, s)) => {
+                out.push_str(&statement(&s));
+                rest = next;
+            }
+            Err(_) => return Err(parser::parse_error(text, rest)),
+        }
+    }
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_round_trips() {
+        for s in &[
+            "edge(a, b).",
+            r#"name("Ann \"Al\" Lee", -42, "tab\there", "", Bob)."#,
+            "path(X, Y)?",
+            "zero().",
+            "path(X, Y) :- path(X, Z), edge(Z, Y), X != Y, Z = \"c d\".",
+            ".decl edge(src: symbol, weight: number)",
+            ".input edge",
+            r#".input edge(filename="edges.csv", delimiter=",", headers="true")"#,
+            r#".output path(format="jsonl")"#,
+            r#".include "lib/graph.dl""#,
+            ".module graph { node(X) :- graph::edge(X, _). .module walk { } }",
+        ] {
+            let parsed = parser::statement(s).unwrap().1;
+            assert_eq!(*s, parsed.to_string());
+            assert_eq!(Ok(("", parsed.clone())), parser::statement(&parsed.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_program_layout() {
+        let messy = "
+% edges
+edge( a,b ).edge(b ,c).   % two on a line
+
+
+
+path(X,Y):-edge(X,Y).
+reachable_through_the_graph(From, To) :- reachable_through_the_graph(From, Via), edge(Via, To), From != To.
+";
+        let tidy = "% edges
+edge(a, b).
+edge(b, c).  % two on a line
+
+path(X, Y) :- edge(X, Y).
+reachable_through_the_graph(From, To) :-
+    reachable_through_the_graph(From, Via),
+    edge(Via, To),
+    From != To.
+";
+        assert_eq!(Ok(tidy.to_string()), program(messy));
+        // formatting is a no-op on formatted code
+        assert_eq!(Ok(tidy.to_string()), program(tidy));
+    }
+
+    #[test]
+    fn test_module_layout() {
+        let messy = ".module graph{
+% nodes
+node(X):-edge(X,_).   % sources
+
+
+.module walk{step(X,Y):-edge(X,Y).}
+}
+.module empty {}
+";
+        let tidy = ".module graph {
+    % nodes
+    node(X) :- edge(X, _).  % sources
+
+    .module walk {
+        step(X, Y) :- edge(X, Y).
+    }
+}
+.module empty {
+}
+";
+        assert_eq!(Ok(tidy.to_string()), program(messy));
+        assert_eq!(Ok(tidy.to_string()), program(tidy));
+        let parsed = parser::statement(messy).unwrap().1;
+        assert_eq!(
+            ".module graph {\n    node(X) :- edge(X, _).\n    .module walk {\n        step(X, Y) :- edge(X, Y).\n    }\n}",
+            statement(&parsed)
+        );
+    }
+
+    #[test]
+    fn test_program_reports_the_bad_line() {
+        assert_eq!(
+            Err(Error::Parse { line: 2, text: "edge(a, .".to_string() }),
+            program("edge(a, b).\nedge(a, .\n")
+        );
+    }
+}
@@ -0,0 +1,347 @@
+#![allow(unused_imports, dead_code)]
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::mem;
+
+use crate::intern::Sym;
+
+/*
+ * storage for the tuples of a single relation
+ */
+
+/// A relation holds fixed-arity tuples of symbols packed back to back in one
+/// buffer, so row `i` of a relation with arity `n` is `data[i * n..(i + 1) * n]`.
+/// No per-tuple allocations, no per-tuple copy of the relation's name.
+///
+/// Relations are sets: pushing a row that's already there does nothing. The
+/// rows are indexed by an open addressing hash table of row numbers so
+/// checking for a duplicate doesn't need a second copy of every tuple.
+#[derive(Clone, Debug)]
+pub struct Relation {
+    arity: usize,
+    len: usize,
+    data: Vec<Sym>,
+    // row numbers, or EMPTY. always a power of two long and at most half full
+    slots: Vec<u32>,
+}
+
+const EMPTY: u32 = u32::MAX;
+
+pub fn hash_row(row: &[Sym]) -> u64 {
+    let mut h = DefaultHasher::new();
+    row.hash(&mut h);
+    h.finish()
+}
+
+impl Relation {
+    pub fn new(arity: usize) -> Relation {
+        Relation {
+            arity,
+            len: 0,
+            data: vec![],
+            slots: vec![EMPTY; 8],
+        }
+    }
+
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// adds a row, returning false if it was already in the relation
+    pub fn push(&mut self, row: &[Sym]) -> bool {
+        assert_eq!(
+            self.arity,
+            row.len(),
+            "tuple does not fit a relation of arity {}",
+            self.arity
+        );
+        let slot = match self.find(row) {
+            Ok(_) => return false,
+            Err(slot) => slot,
+        };
+        self.slots[slot] = self.len as u32;
+        self.data.extend_from_slice(row);
+        self.len += 1;
+        if self.len * 2 > self.slots.len() {
+            self.grow();
+        }
+        true
+    }
+
+    /// takes a row out, returning false if it wasn't there. the last row
+    /// moves into its place, so removing changes the order rows come out in
+    pub fn remove(&mut self, row: &[Sym]) -> bool {
+        let i = match self.find(row) {
+            Ok(i) => i,
+            Err(_) => return false,
+        };
+        let mask = self.slots.len() - 1;
+        // close the gap in the probe sequence by shifting later entries back,
+        // any entry that could have gone in the hole moves there
+        let mut hole = self.slot_of(i);
+        let mut next = (hole + 1) & mask;
+        while self.slots[next] != EMPTY {
+            let home = hash_row(self.row(self.slots[next] as usize)) as usize & mask;
+            if next.wrapping_sub(home) & mask >= next.wrapping_sub(hole) & mask {
+                self.slots[hole] = self.slots[next];
+                hole = next;
+            }
+            next = (next + 1) & mask;
+        }
+        self.slots[hole] = EMPTY;
+
+        let last = self.len - 1;
+        if i != last {
+            let slot = self.slot_of(last);
+            self.slots[slot] = i as u32;
+            let (n, moved) = (self.arity, last * self.arity);
+            self.data.copy_within(moved..moved + n, i * n);
+        }
+        self.data.truncate(last * self.arity);
+        self.len -= 1;
+        true
+    }
+
+    pub fn contains(&self, row: &[Sym]) -> bool {
+        row.len() == self.arity && self.find(row).is_ok()
+    }
+
+    pub fn row(&self, i: usize) -> &[Sym] {
+        &self.data[i * self.arity..(i + 1) * self.arity]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &[Sym]> {
+        // not `chunks_exact`, that can't handle zero-arity relations like `raining()`
+        (0..self.len).map(move |i| self.row(i))
+    }
+
+    pub fn heap_bytes(&self) -> usize {
+        self.data.capacity() * mem::size_of::<Sym>() + self.slots.capacity() * mem::size_of::<u32>()
+    }
+
+    // Ok(row number) if the row is stored, otherwise Err(the empty slot it would go in)
+    fn find(&self, row: &[Sym]) -> Result<usize, usize> {
+        let mask = self.slots.len() - 1;
+        let mut slot = hash_row(row) as usize & mask;
+        loop {
+            match self.slots[slot] {
+                EMPTY => return Err(slot),
+                i if self.row(i as usize) == row => return Ok(i as usize),
+                _ => slot = (slot + 1) & mask,
+            }
+        }
+    }
+
+    // the slot holding row number `i`, which has to be stored
+    fn slot_of(&self, i: usize) -> usize {
+        let mask = self.slots.len() - 1;
+        let mut slot = hash_row(self.row(i)) as usize & mask;
+        while self.slots[slot] != i as u32 {
+            slot = (slot + 1) & mask;
+        }
+        slot
+    }
+
+    fn grow(&mut self) {
+        let mut slots = vec![EMPTY; self.slots.len() * 2];
+        let mask = slots.len() - 1;
+        for i in 0..self.len {
+            let mut slot = hash_row(self.row(i)) as usize & mask;
+            while slots[slot] != EMPTY {
+                slot = (slot + 1) & mask;
+            }
+            slots[slot] = i as u32;
+        }
+        self.slots = slots;
+    }
+}
+
+/// Every change made to one relation's stored rows, oldest first: the rows
+/// packed back to back the way a [`Relation`] keeps them, with the version
+/// each was stored or retracted in alongside. See `RustEngine::as_of`.
+#[derive(Clone, Debug)]
+pub struct Log {
+    arity: usize,
+    data: Vec<Sym>,
+    // never decreasing, so the changes up to a version are a prefix
+    versions: Vec<u64>,
+    // whether each row went in rather than out
+    inserted: Vec<bool>,
+}
+
+impl Log {
+    pub fn new(arity: usize) -> Log {
+        Log {
+            arity,
+            data: vec![],
+            versions: vec![],
+            inserted: vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.versions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.versions.is_empty()
+    }
+
+    pub fn push(&mut self, version: u64, row: &[Sym], inserted: bool) {
+        assert_eq!(self.arity, row.len(), "tuple does not fit a relation of arity {}", self.arity);
+        debug_assert!(self.versions.last().is_none_or(|v| *v <= version));
+        self.data.extend_from_slice(row);
+        self.versions.push(version);
+        self.inserted.push(inserted);
+    }
+
+    /// how many of the changes were made at or before `version`
+    pub fn until(&self, version: u64) -> usize {
+        self.versions.partition_point(|v| *v <= version)
+    }
+
+    /// puts `relation` back the way it was at `version`, undoing the later
+    /// changes newest first
+    pub fn undo(&self, relation: &mut Relation, version: u64) {
+        for i in (self.until(version)..self.len()).rev() {
+            let row = &self.data[i * self.arity..(i + 1) * self.arity];
+            if self.inserted[i] {
+                relation.remove(row);
+            } else {
+                relation.push(row);
+            }
+        }
+    }
+
+    /// forgets the changes made after `version`
+    pub fn truncate(&mut self, version: u64) {
+        let kept = self.until(version);
+        self.data.truncate(kept * self.arity);
+        self.versions.truncate(kept);
+        self.inserted.truncate(kept);
+    }
+
+    pub fn heap_bytes(&self) -> usize {
+        self.data.capacity() * mem::size_of::<Sym>()
+            + self.versions.capacity() * mem::size_of::<u64>()
+            + self.inserted.capacity() * mem::size_of::<bool>()
+    }
+}
+
+/// two relations are equal when they hold the same set of rows, in any order
+impl PartialEq for Relation {
+    fn eq(&self, other: &Relation) -> bool {
+        self.arity == other.arity && self.len == other.len && self.iter().all(|r| other.contains(r))
+    }
+}
+
+#[test]
+fn test_rows_come_back_out() {
+    let mut i = crate::intern::Interner::new();
+    let (a, b, c) = (i.intern("a"), i.intern("b"), i.intern("c"));
+    let mut r = Relation::new(2);
+    r.push(&[a, b]);
+    r.push(&[b, c]);
+    assert_eq!(2, r.len());
+    assert_eq!(vec![&[a, b][..], &[b, c][..]], r.iter().collect::<Vec<_>>());
+}
+
+#[test]
+fn test_zero_arity() {
+    let mut r = Relation::new(0);
+    assert!(r.push(&[]));
+    assert!(!r.push(&[]));
+    assert_eq!(1, r.len());
+    assert_eq!(1, r.iter().count());
+}
+
+#[test]
+fn test_duplicates_are_dropped() {
+    let mut i = crate::intern::Interner::new();
+    let (a, b) = (i.intern("a"), i.intern("b"));
+    let mut r = Relation::new(2);
+    assert!(r.push(&[a, b]));
+    assert!(!r.push(&[a, b]));
+    assert!(r.push(&[b, a]));
+    assert_eq!(2, r.len());
+    assert!(r.contains(&[b, a]));
+    assert!(!r.contains(&[b, b]));
+}
+
+#[test]
+fn test_remove() {
+    let mut i = crate::intern::Interner::new();
+    let syms: Vec<Sym> = (0..50).map(|n| i.intern(&n.to_string())).collect();
+    let mut r = Relation::new(2);
+    for x in &syms {
+        for y in &syms {
+            r.push(&[*x, *y]);
+        }
+    }
+    for x in &syms {
+        assert!(r.remove(&[*x, *x]));
+        assert!(!r.remove(&[*x, *x]));
+    }
+    assert_eq!(50 * 49, r.len());
+    for x in &syms {
+        for y in &syms {
+            assert_eq!(x != y, r.contains(&[*x, *y]));
+        }
+    }
+    assert!(r.push(&[syms[0], syms[0]]));
+    assert_eq!(50 * 49 + 1, r.iter().count());
+}
+
+#[test]
+fn test_survives_growing() {
+    let mut i = crate::intern::Interner::new();
+    let syms: Vec<Sym> = (0..100).map(|n| i.intern(&n.to_string())).collect();
+    let mut r = Relation::new(2);
+    for x in &syms {
+        for y in &syms {
+            assert!(r.push(&[*x, *y]));
+        }
+    }
+    for x in &syms {
+        for y in &syms {
+            assert!(!r.push(&[*x, *y]));
+        }
+    }
+    assert_eq!(100 * 100, r.len());
+}
+
+#[test]
+fn test_log_undoes_what_came_after() {
+    let mut i = crate::intern::Interner::new();
+    let (a, b) = (i.intern("a"), i.intern("b"));
+    let mut r = Relation::new(1);
+    let mut log = Log::new(1);
+    for (version, row, inserted) in [(1, a, true), (2, b, true), (3, a, false), (3, b, false), (4, a, true)] {
+        if inserted {
+            r.push(&[row]);
+        } else {
+            r.remove(&[row]);
+        }
+        log.push(version, &[row], inserted);
+    }
+    assert_eq!(2, log.until(2));
+    let mut then = r.clone();
+    log.undo(&mut then, 2);
+    assert!(then.contains(&[a]) && then.contains(&[b]));
+    log.undo(&mut r, 0);
+    assert!(r.is_empty());
+
+    log.truncate(3);
+    assert_eq!(4, log.len());
+    log.truncate(0);
+    assert!(log.is_empty());
+}
@@ -0,0 +1,134 @@
+#![allow(unused_imports, dead_code)]
+
+use std::fs;
+use std::path::Path;
+
+/*
+ * saves everything an engine knows as a plain .dl program and reads it back:
+ * declarations first, then every stored fact, then the rules in the order
+ * they were pushed, then the constraints. loading a snapshot is just running
+ * that program
+ */
+use crate::ast::{Fact, Statement};
+use crate::engine::{DatalogEngine, RustEngine};
+use crate::error::Error;
+use crate::parser;
+use crate::pretty;
+
+const HEADER: &str = "% datalog snapshot";
+
+/// The statements that rebuild `engine` from scratch. The same state always
+/// dumps to the same statements.
+///
+/// Relations attached read only (like a sqlite table) come out as plain facts,
+/// the snapshot doesn't depend on the database still being there. Only the
+/// latest version goes in, see `RustEngine::as_of`.
+pub fn dump(engine: &RustEngine) -> Vec<Statement> {
+    let mut out = vec![];
+    out.extend(engine.declarations().into_iter().cloned().map(Statement::Declaration));
+    out.extend(engine.facts().into_iter().map(Statement::Fact));
+    out.extend(engine.written_rules().iter().cloned().map(Statement::Rule));
+    out.extend(engine.constraints().into_iter().cloned().map(Statement::Constraint));
+    out
+}
+
+/// `dump` as the text of a .dl file, laid out the way `datalog fmt` would.
+pub fn to_text(engine: &RustEngine) -> String {
+    let mut text = format!("{}\n", HEADER);
+    for statement in dump(engine) {
+        text.push_str(&pretty::statement(&statement));
+        text.push('\n');
+    }
+    text
+}
+
+pub fn save(engine: &RustEngine, path: &Path) -> Result<(), Error> {
+    fs::write(path, to_text(engine)).map_err(|e| Error::io(&path.display().to_string(), e))
+}
+
+/// Runs the declarations, facts, rules and constraints in `text` against `engine`,
+/// returning how many statements that was. Queries and file directives
+/// aren't state and are refused.
+pub fn restore(engine: &mut dyn DatalogEngine, text: &str) -> Result<usize, Error> {
+    let statements = parser::program(text)?;
+    let count = statements.len();
+    for statement in statements {
+        match statement {
+            Statement::Declaration(d) => engine.declare(d)?,
+            Statement::Fact(f) => {
+                engine.push_fact(f)?;
+            }
+            Statement::Rule(r) => engine.push_rule(r)?,
+            Statement::Constraint(c) => engine.push_constraint(c)?,
+            other => return Err(Error::Schema(format!("snapshots don't hold {}", other))),
+        }
+    }
+    Ok(count)
+}
+
+pub fn load(engine: &mut dyn DatalogEngine, path: &Path) -> Result<usize, Error> {
+    let text = fs::read_to_string(path).map_err(|e| Error::io(&path.display().to_string(), e))?;
+    restore(engine, &text).map_err(|e| e.context(&path.display().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Variable;
+
+    const PROGRAM: &str = r#"
+        .decl person(name: symbol, age: number)
+        person("Alice \"Al\" Smith", 30). person(bob, -4).
+        edge(b, c). edge(a, b).
+        path(X, Y) :- edge(X, Y).
+        path(X, Y) :- path(X, Z), edge(Z, Y), X != Y.
+    "#;
+
+    fn ask(e: &dyn DatalogEngine, q: &str) -> Vec<Fact> {
+        match parser::statement(q) {
+            Ok((_, Statement::Query(q))) => e.query(q).unwrap().unwrap_or_default(),
+            x => panic!("not a query {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_dump_is_canonical() {
+        let mut e = RustEngine::new();
+        restore(&mut e, PROGRAM).unwrap();
+        assert_eq!(
+            r#"% datalog snapshot
+.decl person(name: symbol, age: number)
+edge(a, b).
+edge(b, c).
+person("Alice \"Al\" Smith", 30).
+person(bob, -4).
+path(X, Y) :- edge(X, Y).
+path(X, Y) :- path(X, Z), edge(Z, Y), X != Y.
+"#,
+            to_text(&e)
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut e = RustEngine::new();
+        restore(&mut e, PROGRAM).unwrap();
+        let mut copy = RustEngine::new();
+        assert_eq!(Ok(7), restore(&mut copy, &to_text(&e)));
+
+        assert_eq!(to_text(&e), to_text(&copy));
+        assert_eq!(ask(&e, "path(a, X)?").len(), ask(&copy, "path(a, X)?").len());
+        assert!(copy.declaration("person").is_some());
+        assert_eq!(
+            vec![Variable::Fixed("Alice \"Al\" Smith".to_string()), Variable::Fixed("30".to_string())],
+            ask(&copy, "person(N, 30)?")[0].vars
+        );
+    }
+
+    #[test]
+    fn test_only_state_is_restored() {
+        let mut e = RustEngine::new();
+        assert!(restore(&mut e, "edge(a, b). edge(a, X)?").is_err());
+        assert!(restore(&mut e, "edge(a, b). nonsense").is_err());
+    }
+}
@@ -0,0 +1,728 @@
+#![allow(unused_imports, dead_code)]
+
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, NO_PARAMS};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/*
+ * turns a program into a sqlite script, what sql/experiment.sql does by hand:
+ *
+ *   edge(a, b).                      CREATE TABLE "edge" ("c1", "c2");
+ *   path(X, Y) :- edge(X, Y).        INSERT INTO "edge" VALUES ('a', 'b');
+ *   path(X, Y) :- path(X, Z),        CREATE VIEW "path" ("c1", "c2") AS
+ *                 edge(Z, Y).        WITH RECURSIVE "path_rec" ... SELECT * FROM "path_rec";
+ *   path(a, X)?                      SELECT * FROM "path" WHERE "c1" = 'a';
+ *
+ * relations with rules become views, everything else a table. sqlite only
+ * recurses through a single cte reading itself once per select, so mutual
+ * and non-linear recursion get refused rather than translated
+ */
+use crate::ast::{BodyExpression, ColumnType, Constraint, Declaration, Directive, Fact, Rule, Statement, Variable};
+use crate::engine::{DatalogEngine, RustEngine};
+use crate::error::Error;
+use crate::modules;
+use crate::sqlite::identifier;
+use crate::strata;
+
+/// The sqlite script for `statements`: a table for each base relation with
+/// its facts inserted, a view for each derived relation and a select for
+/// each query and `.output`. `.input` files aren't read, their tables are
+/// left for whoever runs the script to fill.
+///
+/// The program is loaded into an engine first, so anything the engine would
+/// refuse is refused here too. `.module`s are flattened, but `.include`s
+/// have to be read in beforehand, see `modules::include`.
+pub fn compile(statements: &[Statement]) -> Result<String, Error> {
+    // a retracted fact never made it as far as the script goes
+    let mut kept: Vec<Statement> = vec![];
+    for statement in modules::flatten(statements.to_vec(), &|_| false) {
+        match statement {
+            Statement::Retract(f) => kept.retain(|s| *s != Statement::Fact(f.clone())),
+            s => kept.push(s),
+        }
+    }
+    let statements = &kept;
+    let mut engine = RustEngine::new();
+    let mut relations = Relations::default();
+    for statement in statements {
+        match statement {
+            Statement::Declaration(d) => {
+                engine.declare(d.clone())?;
+                relations.declare(d)?;
+            }
+            Statement::Fact(f) => {
+                engine.push_fact(f.clone())?;
+                relations.saw(&f.name, f.vars.len())?;
+            }
+            Statement::Rule(r) => {
+                engine.push_rule(r.clone())?;
+                counts(statement, &r.body)?;
+                relations.saw(&r.head.name, r.head.vars.len())?;
+                for expression in &r.body {
+                    if let BodyExpression::Fact(f) | BodyExpression::Not(f) = expression {
+                        relations.saw(&f.name, f.vars.len())?;
+                    }
+                }
+            }
+            Statement::Constraint(c) => {
+                engine.push_constraint(c.clone())?;
+                counts(statement, &c.body)?;
+                for expression in &c.body {
+                    if let BodyExpression::Fact(f) | BodyExpression::Not(f) = expression {
+                        relations.saw(&f.name, f.vars.len())?;
+                    }
+                }
+            }
+            Statement::Query(q) => relations.saw(&q.name, q.vars.len())?,
+            Statement::AsOf(_) => {
+                return Err(Error::Evaluation(format!(
+                    "{} can't be compiled, the script only has the latest facts",
+                    statement
+                )))
+            }
+            Statement::Include(_) | Statement::Module(_) => {
+                return Err(Error::Schema(format!("{} can't be compiled until it's been read in", statement)))
+            }
+            Statement::Retract(_) | Statement::Input(_) | Statement::Output(_) => {}
+        }
+    }
+    let derived = |name: &str| engine.written_rules().iter().any(|r| r.head.name == name);
+
+    let mut out = String::new();
+    for name in &relations.order {
+        if !derived(name) {
+            let columns: Vec<String> = relations.columns[name]
+                .iter()
+                .map(|(column, kind)| match kind {
+                    Some(ColumnType::Symbol) => format!("{} TEXT", identifier(column)),
+                    Some(ColumnType::Number) => format!("{} INTEGER", identifier(column)),
+                    None => identifier(column),
+                })
+                .collect();
+            writeln!(out, "CREATE TABLE {} ({});", identifier(name), columns.join(", ")).unwrap();
+        }
+    }
+    for statement in statements {
+        match statement {
+            Statement::Fact(f) if !derived(&f.name) => {
+                writeln!(out, "INSERT INTO {} VALUES ({});", identifier(&f.name), values(f)).unwrap()
+            }
+            Statement::Input(d) => {
+                writeln!(out, "-- {}: fill {} from outside", statement, identifier(&d.relation)).unwrap()
+            }
+            _ => {}
+        }
+    }
+
+    let rules: Vec<_> = engine.rules().iter().collect();
+    for stratum in strata::strata(&rules) {
+        let rules: Vec<&Rule> = stratum.iter().map(|i| &engine.written_rules()[*i]).collect();
+        let facts: Vec<&Fact> = statements
+            .iter()
+            .filter_map(|s| match s {
+                Statement::Fact(f) if f.name == rules[0].head.name => Some(f),
+                _ => None,
+            })
+            .collect();
+        out.push_str(&view(&relations, &rules, &facts)?);
+    }
+
+    for statement in statements {
+        match statement {
+            Statement::Query(q) => {
+                writeln!(out, "-- {}?", q).unwrap();
+                writeln!(out, "{};", query(&relations, q)).unwrap();
+            }
+            Statement::Output(d) => {
+                writeln!(out, "-- {}", statement).unwrap();
+                writeln!(out, "SELECT * FROM {};", identifier(&d.relation)).unwrap();
+            }
+            Statement::Constraint(c) => {
+                writeln!(out, "-- {} any rows here break it", c).unwrap();
+                writeln!(out, "{};", violations(&relations, c)).unwrap();
+            }
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+/// A [`DatalogEngine`] that answers queries by running the compiled program
+/// in a fresh in-memory sqlite database, there to check the compiler against
+/// the other engines more than to be fast.
+#[derive(Debug, Default)]
+pub struct SqliteEngine {
+    program: Vec<Statement>,
+    relations: Relations,
+    // keeps the facts checked as they come in, without compiling every time
+    checked: RustEngine,
+    // the program and its relations as they were at `begin`
+    saved: Option<(Vec<Statement>, Relations)>,
+}
+
+impl SqliteEngine {
+    pub fn new() -> SqliteEngine {
+        SqliteEngine::default()
+    }
+}
+
+impl DatalogEngine for SqliteEngine {
+    fn push_fact(&mut self, fact: Fact) -> Result<bool, Error> {
+        self.relations.saw(&fact.name, fact.vars.len())?;
+        let new = self.checked.push_fact(fact.clone())?;
+        if new {
+            self.program.push(Statement::Fact(fact));
+        }
+        Ok(new)
+    }
+
+    // rules sqlite can't recurse through get refused here rather than at query time
+    fn push_rule(&mut self, rule: Rule) -> Result<(), Error> {
+        let mut rules: Vec<Statement> = self
+            .program
+            .iter()
+            .filter(|s| !matches!(s, Statement::Fact(_)))
+            .cloned()
+            .collect();
+        rules.push(Statement::Rule(rule.clone()));
+        compile(&rules)?;
+        self.checked.push_rule(rule.clone())?;
+        self.relations.saw(&rule.head.name, rule.head.vars.len())?;
+        for expression in &rule.body {
+            if let BodyExpression::Fact(f) | BodyExpression::Not(f) = expression {
+                self.relations.saw(&f.name, f.vars.len())?;
+            }
+        }
+        self.program.push(Statement::Rule(rule));
+        Ok(())
+    }
+
+    fn query(&self, q: Fact) -> Result<Option<Vec<Fact>>, Error> {
+        match self.relations.columns.get(&q.name) {
+            Some(columns) if columns.len() == q.vars.len() => {}
+            _ => return Ok(None),
+        }
+        let fail = |e: rusqlite::Error| Error::Evaluation(format!("sqlite: {}", e));
+        let db = Connection::open_in_memory().map_err(fail)?;
+        db.execute_batch(&compile(&self.program)?).map_err(fail)?;
+        let mut select = db.prepare(&query(&self.relations, &q)).map_err(fail)?;
+        let mut rows = select.query(NO_PARAMS).map_err(fail)?;
+        let mut answers = vec![];
+        while let Some(row) = rows.next().map_err(fail)? {
+            let mut vars = Vec::with_capacity(q.vars.len());
+            for i in 0..q.vars.len() {
+                vars.push(Variable::Fixed(match row.get_raw(i) {
+                    ValueRef::Integer(n) => n.to_string(),
+                    ValueRef::Real(f) => f.to_string(),
+                    ValueRef::Text(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+                    ValueRef::Null | ValueRef::Blob(_) => {
+                        return Err(Error::Evaluation(format!("{} came back with a NULL or a blob", q)))
+                    }
+                }));
+            }
+            answers.push(Fact {
+                name: q.name.clone(),
+                vars,
+            });
+        }
+        Ok(Some(answers))
+    }
+
+    fn retract_fact(&mut self, fact: Fact) -> Result<bool, Error> {
+        let gone = self.checked.retract_fact(fact.clone())?;
+        if gone {
+            self.program.retain(|s| *s != Statement::Fact(fact.clone()));
+        }
+        Ok(gone)
+    }
+
+    fn push_constraint(&mut self, constraint: Constraint) -> Result<(), Error> {
+        self.checked.push_constraint(constraint.clone())?;
+        for expression in &constraint.body {
+            if let BodyExpression::Fact(f) | BodyExpression::Not(f) = expression {
+                self.relations.saw(&f.name, f.vars.len())?;
+            }
+        }
+        self.program.push(Statement::Constraint(constraint));
+        Ok(())
+    }
+
+    fn begin(&mut self) -> Result<(), Error> {
+        self.checked.begin()?;
+        self.saved = Some((self.program.clone(), self.relations.clone()));
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), Error> {
+        let saved = self.saved.take();
+        // a commit that fails has rolled the checked engine back, the program follows it
+        let result = self.checked.commit();
+        if let (Err(_), Some((program, relations))) = (&result, saved) {
+            self.program = program;
+            self.relations = relations;
+        }
+        result
+    }
+
+    fn rollback(&mut self) -> Result<(), Error> {
+        self.checked.rollback()?;
+        if let Some((program, relations)) = self.saved.take() {
+            self.program = program;
+            self.relations = relations;
+        }
+        Ok(())
+    }
+
+    fn declare(&mut self, decl: Declaration) -> Result<(), Error> {
+        self.checked.declare(decl.clone())?;
+        self.relations.declare(&decl)?;
+        self.program.push(Statement::Declaration(decl));
+        Ok(())
+    }
+
+    fn declaration(&self, name: &str) -> Option<&Declaration> {
+        self.checked.declaration(name)
+    }
+}
+
+// every relation the program mentions, in the order it first shows up, and
+// its columns: named and typed by its .decl, otherwise c1, c2, ...
+#[derive(Clone, Debug, Default)]
+struct Relations {
+    order: Vec<String>,
+    columns: HashMap<String, Vec<(String, Option<ColumnType>)>>,
+}
+
+impl Relations {
+    fn declare(&mut self, d: &Declaration) -> Result<(), Error> {
+        self.saw(&d.name, d.columns.len())?;
+        let columns = d.columns.iter().map(|c| (c.name.clone(), Some(c.kind))).collect();
+        self.columns.insert(d.name.clone(), columns);
+        Ok(())
+    }
+
+    fn saw(&mut self, name: &str, arity: usize) -> Result<(), Error> {
+        match self.columns.get(name) {
+            Some(columns) if columns.len() != arity => Err(Error::Schema(format!(
+                "{} is used with {} and {} columns, a table only gets one",
+                name,
+                columns.len(),
+                arity
+            ))),
+            Some(_) => Ok(()),
+            None => {
+                self.order.push(name.to_string());
+                let columns = (1..=arity).map(|i| (format!("c{}", i), None)).collect();
+                self.columns.insert(name.to_string(), columns);
+                Ok(())
+            }
+        }
+    }
+
+    fn names(&self, relation: &str) -> Vec<String> {
+        self.columns[relation].iter().map(|(c, _)| identifier(c)).collect()
+    }
+}
+
+// the view for one stratum, which has to derive a single relation
+fn view(relations: &Relations, rules: &[&Rule], facts: &[&Fact]) -> Result<String, Error> {
+    let name = &rules[0].head.name;
+    if let Some(other) = rules.iter().find(|r| &r.head.name != name) {
+        return Err(Error::Evaluation(format!(
+            "{} and {} depend on each other, sqlite can't recurse through both",
+            name, other.head.name
+        )));
+    }
+    let reads_itself = |r: &Rule| {
+        r.body
+            .iter()
+            .filter(|e| matches!(e, BodyExpression::Fact(f) if &f.name == name))
+            .count()
+    };
+    if let Some(rule) = rules.iter().find(|r| reads_itself(r) > 1) {
+        return Err(Error::Evaluation(format!(
+            "{} reads {} more than once, sqlite only recurses through one",
+            rule, name
+        )));
+    }
+
+    let columns = relations.names(name).join(", ");
+    let recursive = rules.iter().any(|r| reads_itself(r) > 0);
+    // inside the recursive cte the relation goes by the cte's name
+    let cte = format!("{}_rec", name);
+    let own = if recursive { Some((name.as_str(), cte.as_str())) } else { None };
+
+    // sqlite wants what starts the recursion before the selects that continue it
+    let mut selects: Vec<String> = facts.iter().map(|f| format!("SELECT {}", values(f))).collect();
+    let (base, step): (Vec<&Rule>, Vec<&Rule>) = rules.iter().partition(|r| reads_itself(r) == 0);
+    for rule in base.iter().chain(step.iter()) {
+        selects.push(select(relations, rule, own));
+    }
+    if base.is_empty() && facts.is_empty() {
+        let nulls = vec!["NULL"; relations.columns[name].len()];
+        selects.insert(0, format!("SELECT {} WHERE 0", nulls.join(", ")));
+    }
+    let union = selects.join("\n    UNION\n    ");
+
+    let mut out = String::new();
+    for rule in rules {
+        writeln!(out, "-- {}", rule).unwrap();
+    }
+    if recursive {
+        writeln!(out, "CREATE VIEW {} ({}) AS", identifier(name), columns).unwrap();
+        writeln!(out, "WITH RECURSIVE {} ({}) AS (", identifier(&cte), columns).unwrap();
+        writeln!(out, "    {}", union).unwrap();
+        writeln!(out, ")").unwrap();
+        writeln!(out, "SELECT * FROM {};", identifier(&cte)).unwrap();
+    } else {
+        writeln!(out, "CREATE VIEW {} ({}) AS", identifier(name), columns).unwrap();
+        writeln!(out, "    {};", union).unwrap();
+    }
+    Ok(out)
+}
+
+// one rule as a select: each body atom joined in as t0, t1, ... with a
+// variable standing for the first column it shows up in
+// sqlite could count with a subquery, but nothing here writes one yet
+fn counts(statement: &Statement, body: &[BodyExpression]) -> Result<(), Error> {
+    match body.iter().any(|e| matches!(e, BodyExpression::Count(_))) {
+        true => Err(Error::Evaluation(format!("{} can't be compiled, the script has no counts yet", statement))),
+        false => Ok(()),
+    }
+}
+
+fn select(relations: &Relations, rule: &Rule, own: Option<(&str, &str)>) -> String {
+    let mut from = vec![];
+    let mut conditions = vec![];
+    let mut bound: HashMap<&str, String> = HashMap::new();
+
+    let atoms = rule.body.iter().filter_map(|e| match e {
+        BodyExpression::Fact(f) => Some(f),
+        _ => None,
+    });
+    for (i, atom) in atoms.enumerate() {
+        let table = match own {
+            Some((name, cte)) if name == atom.name => cte,
+            _ => &atom.name,
+        };
+        from.push(format!("{} AS t{}", identifier(table), i));
+        for (column, v) in relations.names(&atom.name).iter().zip(&atom.vars) {
+            let column = format!("t{}.{}", i, column);
+            match v {
+                Variable::Fixed(c) => conditions.push(format!("{} = {}", column, literal(c))),
+                _ if v.is_wildcard() => {}
+                Variable::Free(x) => match bound.get(x.as_str()) {
+                    Some(first) => conditions.push(format!("{} = {}", column, first)),
+                    None => {
+                        bound.insert(x, column);
+                    }
+                },
+            }
+        }
+    }
+
+    // X = Y binds whichever side isn't bound yet, the same as safety::check
+    let expression = |bound: &HashMap<&str, String>, v: &Variable| match v {
+        Variable::Fixed(c) => Some(literal(c)),
+        Variable::Free(x) => bound.get(x.as_str()).cloned(),
+    };
+    let mut grew = true;
+    while grew {
+        grew = false;
+        for e in &rule.body {
+            if let BodyExpression::Equals(e) = e {
+                if !e.equals {
+                    continue;
+                }
+                let unbound = match (expression(&bound, &e.left), expression(&bound, &e.right)) {
+                    (Some(known), None) => Some((&e.right, known)),
+                    (None, Some(known)) => Some((&e.left, known)),
+                    _ => None,
+                };
+                if let Some((Variable::Free(x), known)) = unbound {
+                    bound.insert(x, known);
+                    grew = true;
+                }
+            }
+        }
+    }
+
+    for e in &rule.body {
+        match e {
+            BodyExpression::Fact(_) => {}
+            BodyExpression::Equals(e) => {
+                let left = expression(&bound, &e.left).unwrap();
+                let right = expression(&bound, &e.right).unwrap();
+                // X = Y that bound one to the other says nothing new
+                if !(e.equals && left == right) {
+                    conditions.push(format!("{} {} {}", left, if e.equals { "=" } else { "<>" }, right));
+                }
+            }
+            BodyExpression::Not(f) => {
+                let matches: Vec<String> = relations
+                    .names(&f.name)
+                    .iter()
+                    .zip(&f.vars)
+                    .filter(|(_, v)| !v.is_wildcard())
+                    .map(|(column, v)| format!("n.{} = {}", column, expression(&bound, v).unwrap()))
+                    .collect();
+                let mut exists = format!("SELECT 1 FROM {} AS n", identifier(&f.name));
+                if !matches.is_empty() {
+                    write!(exists, " WHERE {}", matches.join(" AND ")).unwrap();
+                }
+                conditions.push(format!("NOT EXISTS ({})", exists));
+            }
+            BodyExpression::Count(_) => unreachable!("compile refuses counts"),
+        }
+    }
+
+    let head: Vec<String> = rule.head.vars.iter().map(|v| expression(&bound, v).unwrap()).collect();
+    let mut out = format!("SELECT {}", head.join(", "));
+    if !from.is_empty() {
+        write!(out, " FROM {}", from.join(", ")).unwrap();
+    }
+    if !conditions.is_empty() {
+        write!(out, " WHERE {}", conditions.join(" AND ")).unwrap();
+    }
+    out
+}
+
+// a constraint as the select of everything its body matches, by the values of
+// its named variables (or just 1 when it has none)
+fn violations(relations: &Relations, c: &Constraint) -> String {
+    let mut vars = vec![];
+    for e in &c.body {
+        if let BodyExpression::Fact(f) = e {
+            for v in &f.vars {
+                if let Variable::Free(_) = v {
+                    if !v.is_wildcard() && !vars.contains(v) {
+                        vars.push(v.clone());
+                    }
+                }
+            }
+        }
+    }
+    if vars.is_empty() {
+        vars.push(Variable::Fixed("1".to_string()));
+    }
+    let rule = Rule {
+        head: Fact {
+            name: String::new(),
+            vars,
+        },
+        body: c.body.clone(),
+    };
+    select(relations, &rule, None)
+}
+
+fn query(relations: &Relations, q: &Fact) -> String {
+    let mut conditions = vec![];
+    let mut first: HashMap<&str, &String> = HashMap::new();
+    let columns = relations.names(&q.name);
+    for (column, v) in columns.iter().zip(&q.vars) {
+        match v {
+            Variable::Fixed(c) => conditions.push(format!("{} = {}", column, literal(c))),
+            _ if v.is_wildcard() => {}
+            Variable::Free(x) => match first.get(x.as_str()) {
+                Some(earlier) => conditions.push(format!("{} = {}", column, earlier)),
+                None => {
+                    first.insert(x, column);
+                }
+            },
+        }
+    }
+    let mut out = format!("SELECT * FROM {}", identifier(&q.name));
+    if !conditions.is_empty() {
+        write!(out, " WHERE {}", conditions.join(" AND ")).unwrap();
+    }
+    out
+}
+
+fn values(f: &Fact) -> String {
+    let values: Vec<String> = f
+        .vars
+        .iter()
+        .map(|v| match v {
+            Variable::Fixed(c) | Variable::Free(c) => literal(c),
+        })
+        .collect();
+    values.join(", ")
+}
+
+// 30 stays a number, everything else is a string. so does 007, sqlite
+// would hand it back as 7
+fn literal(constant: &str) -> String {
+    if constant.parse::<i64>().is_ok_and(|n| n.to_string() == constant) {
+        constant.to_string()
+    } else {
+        format!("'{}'", constant.replace('\'', "''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+    use rusqlite::{Connection, NO_PARAMS};
+
+    // runs the compiled program in sqlite and returns what `select` gives, sorted
+    fn run(program: &str, select: &str) -> Vec<Vec<String>> {
+        let script = compile(&parser::program(program).unwrap()).unwrap();
+        let db = Connection::open_in_memory().unwrap();
+        db.execute_batch(&script).unwrap_or_else(|e| panic!("{}\n{}", e, script));
+        let mut statement = db.prepare(select).unwrap();
+        let width = statement.column_count();
+        let mut rows: Vec<Vec<String>> = statement
+            .query_map(NO_PARAMS, |row| {
+                Ok((0..width)
+                    .map(|i| match row.get_raw(i) {
+                        rusqlite::types::ValueRef::Integer(n) => n.to_string(),
+                        rusqlite::types::ValueRef::Text(t) => String::from_utf8(t.to_vec()).unwrap(),
+                        other => format!("{:?}", other),
+                    })
+                    .collect())
+            })
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        rows.sort();
+        rows
+    }
+
+    // what the engine answers for the same program
+    fn engine(program: &str, q: &str) -> Vec<Vec<String>> {
+        let mut e = RustEngine::new();
+        for s in parser::program(program).unwrap() {
+            match s {
+                Statement::Fact(f) => {
+                    e.push_fact(f).unwrap();
+                }
+                Statement::Rule(r) => e.push_rule(r).unwrap(),
+                Statement::Declaration(d) => e.declare(d).unwrap(),
+                x => panic!("not in test programs {:?}", x),
+            }
+        }
+        let mut rows: Vec<Vec<String>> = e
+            .query(parser::query(q).unwrap())
+            .unwrap()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|f| {
+                f.vars
+                    .into_iter()
+                    .map(|v| match v {
+                        Variable::Fixed(s) | Variable::Free(s) => s,
+                    })
+                    .collect()
+            })
+            .collect();
+        rows.sort();
+        rows
+    }
+
+    const GRAPH: &str = r#"
+        edge(a, b). edge(b, c). edge(c, d). edge(x, "it's").
+        path(X, Y) :- edge(X, Y).
+        path(X, Y) :- path(X, Z), edge(Z, Y).
+        path(X, Y) :- edge(X, Z), path(Z, Y).
+        node(X) :- edge(X, Y).
+        node(Y) :- edge(X, Y).
+        sink(X) :- node(X), !edge(X, Y), Y = d.
+        self(X) :- path(X, Y), X = Y.
+        other(X, Y) :- node(X), node(Y), X != Y, W = X, path(W, Y).
+        start(a).
+        start(X) :- edge(X, b).
+        never(X) :- node(X), X != X.
+        leaf(X) :- node(X), !edge(X, _).
+        parent(X) :- edge(X, _), edge(_, X).
+    "#;
+
+    #[test]
+    fn test_agrees_with_the_engine() {
+        for (relation, q) in &[
+            ("path", "path(X, Y)"),
+            ("sink", "sink(X)"),
+            ("self", "self(X)"),
+            ("other", "other(X, Y)"),
+            ("start", "start(X)"),
+            ("never", "never(X)"),
+            ("leaf", "leaf(X)"),
+            ("parent", "parent(X)"),
+        ] {
+            let select = format!("SELECT * FROM \"{}\"", relation);
+            assert_eq!(engine(GRAPH, q), run(GRAPH, &select), "{}", relation);
+        }
+    }
+
+    #[test]
+    fn test_declared_columns() {
+        let program = "
+            .decl age(name: symbol, years: number)
+            age(alice, 30). age(bob, 7).
+            adult(N) :- age(N, 30).
+        ";
+        assert_eq!(vec![vec!["alice".to_string()]], run(program, "SELECT * FROM adult"));
+        assert_eq!(vec![vec!["bob".to_string()]], run(program, "SELECT name FROM age WHERE years < 10"));
+    }
+
+    #[test]
+    fn test_queries() {
+        let script = compile(&parser::program("edge(a, a). edge(a, b). edge(X, X)? edge(a, Y)?").unwrap()).unwrap();
+        assert!(script.contains("SELECT * FROM \"edge\" WHERE \"c2\" = \"c1\";"), "{}", script);
+        assert!(script.contains("SELECT * FROM \"edge\" WHERE \"c1\" = 'a';"), "{}", script);
+    }
+
+    #[test]
+    fn test_constraints() {
+        let program = "edge(a, b). edge(b, c). :- edge(X, Y), edge(Y, X). :- edge(_, a).";
+        let script = compile(&parser::program(program).unwrap()).unwrap();
+        assert!(script.contains("-- :- edge(X, Y), edge(Y, X). any rows here break it"), "{}", script);
+        let violations = r#"SELECT * FROM edge AS t0, edge AS t1 WHERE t1.c1 = t0.c2 AND t1.c2 = t0.c1"#;
+        assert!(run(program, violations).is_empty());
+    }
+
+    #[test]
+    fn test_retracted_facts_are_left_out() {
+        let program = "edge(a, b). edge(b, c). ~edge(a, b). start(a). start(b). ~start(a). edge(a, b).";
+        assert_eq!(vec![vec!["a", "b"], vec!["b", "c"]], run(program, "SELECT * FROM edge"));
+        assert_eq!(vec![vec!["b"]], run(program, "SELECT * FROM start"));
+    }
+
+    #[test]
+    fn test_transactions() {
+        let mut e = SqliteEngine::new();
+        let edge = parser::query("edge(X, Y)").unwrap();
+        e.push_fact(parser::query("edge(a, b)").unwrap()).unwrap();
+        e.begin().unwrap();
+        e.push_fact(parser::query("edge(b, a)").unwrap()).unwrap();
+        e.push_fact(parser::query("other(b, a, c)").unwrap()).unwrap();
+        assert_eq!(2, e.query(edge.clone()).unwrap().unwrap().len());
+        e.rollback().unwrap();
+        assert_eq!(1, e.query(edge.clone()).unwrap().unwrap().len());
+        assert_eq!(Ok(None), e.query(parser::query("other(X, Y, Z)").unwrap()));
+
+        let constraint = match parser::program(":- edge(X, Y), edge(Y, X).").unwrap().remove(0) {
+            Statement::Constraint(c) => c,
+            other => panic!("{}", other),
+        };
+        e.begin().unwrap();
+        e.push_constraint(constraint).unwrap();
+        e.push_fact(parser::query("edge(b, a)").unwrap()).unwrap();
+        assert!(matches!(e.commit(), Err(Error::Constraint(_))));
+        assert_eq!(1, e.query(edge).unwrap().unwrap().len());
+    }
+
+    #[test]
+    fn test_refused() {
+        let refused = |program: &str| compile(&parser::program(program).unwrap()).unwrap_err();
+        assert!(matches!(refused("p(X) :- q(X). q(X) :- p(X)."), Error::Evaluation(_)));
+        assert!(matches!(refused("p(X, Y) :- p(X, Z), p(Z, Y)."), Error::Evaluation(_)));
+        assert!(matches!(refused("p(X) :- q(X, Y). q(a)."), Error::Schema(_)));
+        assert!(matches!(refused("p(X) :- q(Y)."), Error::Safety(_)));
+        assert!(matches!(refused("p(X) :- q(X), !p(X)."), Error::Stratification(_)));
+        assert!(matches!(refused("q(a, a). :- q(X, X)."), Error::Constraint(_)));
+        assert!(matches!(refused("q(a). q(X) as of 1?"), Error::Evaluation(_)));
+        assert!(matches!(refused("p(X, N) :- q(X), N = count : { q(X) }."), Error::Evaluation(_)));
+    }
+}
@@ -0,0 +1,275 @@
+#![allow(unused_imports, dead_code)]
+extern crate rusqlite;
+
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OpenFlags, NO_PARAMS};
+use std::path::{Path, PathBuf};
+
+/*
+ * reads a table out of an existing sqlite database as a base relation, so
+ * rules can run over a production snapshot without exporting it first:
+ *
+ *   .decl person(name: symbol, age: number)
+ *   .input person from sqlite "crm.db" table "people" columns "full_name, age"
+ *
+ * the database is opened read only, nothing is ever written back
+ */
+use crate::ast::{ColumnType, Declaration, Directive, Fact, Variable};
+use crate::engine::DatalogEngine;
+use crate::error::Error;
+
+/// Where to read a relation from, out of an `.input ... from sqlite` directive.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SqliteOptions {
+    pub path: PathBuf,
+    pub table: String,
+    /// the table columns that make up the relation, in order. Empty means the
+    /// declared column names, or every column of the table when there's no `.decl`
+    pub columns: Vec<String>,
+}
+
+impl SqliteOptions {
+    pub fn input(d: &Directive) -> Result<SqliteOptions, Error> {
+        let mut opts = SqliteOptions {
+            path: PathBuf::new(),
+            table: String::new(),
+            columns: vec![],
+        };
+        for (key, value) in &d.params {
+            match key.as_str() {
+                "format" => {}
+                "filename" => opts.path = PathBuf::from(value),
+                "table" => opts.table = value.clone(),
+                "columns" => opts.columns = value.split(',').map(|c| c.trim().to_string()).collect(),
+                _ => return Err(Error::Schema(format!("unknown parameter {} for {}", key, d.relation))),
+            }
+        }
+        if opts.path.as_os_str().is_empty() || opts.table.is_empty() {
+            return Err(Error::Schema(format!("{} needs a database file and a table", d.relation)));
+        }
+        Ok(opts)
+    }
+
+    /// relative paths are taken relative to `dir`, like the directory of the program file
+    pub fn relative_to(mut self, dir: &Path) -> SqliteOptions {
+        if self.path.is_relative() {
+            self.path = dir.join(&self.path);
+        }
+        self
+    }
+}
+
+// "people" -> "\"people\"", so table and column names can't turn into more sql
+pub fn identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Loads every row of the table in `opts` as a fact of `relation`, returning
+/// how many new facts that made.
+///
+/// Integers and text come in as they are. For a `number` column in the
+/// relation's `.decl` the value has to be an integer (or a real or text
+/// holding one). NULLs and blobs have no datalog equivalent and are errors,
+/// and one anywhere in the table means none of its rows go in.
+pub fn import(engine: &mut dyn DatalogEngine, relation: &str, opts: &SqliteOptions) -> Result<usize, Error> {
+    let source = format!("{} table {}", opts.path.display(), opts.table);
+    let fail = |e: rusqlite::Error| Error::io(&source, e);
+    let db = Connection::open_with_flags(&opts.path, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(&fail)?;
+    let decl = engine.declaration(relation).cloned();
+
+    let columns = match (&decl, opts.columns.is_empty()) {
+        (_, false) => opts.columns.iter().map(|c| identifier(c)).collect::<Vec<_>>().join(", "),
+        (Some(decl), true) => decl.columns.iter().map(|c| identifier(&c.name)).collect::<Vec<_>>().join(", "),
+        (None, true) => "*".to_string(),
+    };
+    let mut select = db
+        .prepare(&format!("SELECT {} FROM {}", columns, identifier(&opts.table)))
+        .map_err(&fail)?;
+    let names: Vec<String> = select.column_names().iter().map(|c| c.to_string()).collect();
+    if let Some(decl) = &decl {
+        if decl.columns.len() != names.len() {
+            let e = Error::Schema(format!(
+                "{} is declared with {} columns, the table gives {}",
+                relation,
+                decl.columns.len(),
+                names.len()
+            ));
+            return Err(e.context(&source));
+        }
+    }
+
+    // every row is converted before any goes in, so a bad one leaves the
+    // relation as it was
+    let mut rows = select.query(NO_PARAMS).map_err(&fail)?;
+    let mut facts = vec![];
+    while let Some(row) = rows.next().map_err(&fail)? {
+        let mut vars = Vec::with_capacity(names.len());
+        for (i, name) in names.iter().enumerate() {
+            let kind = decl
+                .as_ref()
+                .and_then(|d| d.columns.get(i))
+                .map_or(ColumnType::Symbol, |c| c.kind);
+            match constant(row.get_raw(i), kind) {
+                Ok(s) => vars.push(Variable::Fixed(s)),
+                Err(e) => {
+                    let e = Error::Schema(format!("{} {}", name, e));
+                    return Err(e.context(&format!("{}: row {}", source, facts.len() + 1)));
+                }
+            }
+        }
+        facts.push(Fact {
+            name: relation.to_string(),
+            vars,
+        });
+    }
+    let mut added = 0;
+    for (n, fact) in facts.into_iter().enumerate() {
+        if engine.push_fact(fact).map_err(|e| e.context(&format!("{}: row {}", source, n + 1)))? {
+            added += 1;
+        }
+    }
+    Ok(added)
+}
+
+fn constant(v: ValueRef, kind: ColumnType) -> Result<String, String> {
+    let text = |bytes: &[u8]| match std::str::from_utf8(bytes) {
+        Ok(s) => Ok(s.to_string()),
+        Err(_) => Err("is not utf-8 text".to_string()),
+    };
+    match (v, kind) {
+        (ValueRef::Integer(n), _) => Ok(n.to_string()),
+        (ValueRef::Real(f), ColumnType::Symbol) => Ok(f.to_string()),
+        (ValueRef::Real(f), ColumnType::Number) if f.fract() == 0.0 => Ok((f as i64).to_string()),
+        (ValueRef::Text(bytes), ColumnType::Symbol) => text(bytes),
+        (ValueRef::Text(bytes), ColumnType::Number) => {
+            let s = text(bytes)?;
+            match s.trim().parse::<i64>() {
+                Ok(n) => Ok(n.to_string()),
+                Err(_) => Err(format!("{:?} is not a number", s)),
+            }
+        }
+        (ValueRef::Real(f), ColumnType::Number) => Err(format!("{} is not a whole number", f)),
+        (ValueRef::Null, _) => Err("is NULL".to_string()),
+        (ValueRef::Blob(_), _) => Err("is a blob".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Statement;
+    use crate::engine::RustEngine;
+    use crate::parser;
+
+    // a throwaway database in the temp dir, removed when dropped
+    struct Db(PathBuf);
+
+    impl Db {
+        fn new(name: &str, sql: &str) -> Db {
+            let path = std::env::temp_dir().join(format!("datalog-{}-{}.db", name, std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            Connection::open(&path).unwrap().execute_batch(sql).unwrap();
+            Db(path)
+        }
+    }
+
+    impl Drop for Db {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn input(e: &mut RustEngine, db: &Db, program: &str) -> Result<usize, Error> {
+        let mut result = Ok(0);
+        for s in parser::statements(program).unwrap().1 {
+            match s {
+                Statement::Declaration(d) => e.declare(d).unwrap(),
+                Statement::Input(d) => {
+                    let opts = SqliteOptions::input(&d).unwrap().relative_to(db.0.parent().unwrap());
+                    result = import(e, &d.relation, &opts);
+                }
+                x => panic!("not in test programs {:?}", x),
+            }
+        }
+        result
+    }
+
+    fn ask(e: &RustEngine, q: &str) -> Vec<Vec<Variable>> {
+        match parser::statement(q) {
+            Ok((_, Statement::Query(q))) => e.query(q).unwrap().unwrap().into_iter().map(|f| f.vars).collect(),
+            x => panic!("not a query {:?}", x),
+        }
+    }
+
+    const PEOPLE: &str = "
+        CREATE TABLE people (id INTEGER PRIMARY KEY, full_name TEXT, age REAL, city TEXT);
+        INSERT INTO people (full_name, age, city) VALUES ('Alice Smith', 30.0, 'nyc'), ('bob', 41, NULL);
+    ";
+
+    #[test]
+    fn test_columns_mapped_and_converted() {
+        let db = Db::new("mapped", PEOPLE);
+        let mut e = RustEngine::new();
+        let file = db.0.file_name().unwrap().to_str().unwrap().to_string();
+        let program = format!(
+            r#".decl person(name: symbol, age: number)
+               .input person from sqlite "{}" table "people" columns "full_name, age""#,
+            file
+        );
+        assert_eq!(Ok(2), input(&mut e, &db, &program));
+        assert_eq!(
+            vec![vec![Variable::Fixed("Alice Smith".to_string()), Variable::Fixed("30".to_string())]],
+            ask(&e, "person(N, 30)?")
+        );
+    }
+
+    #[test]
+    fn test_whole_table_without_a_decl() {
+        let db = Db::new("whole", "CREATE TABLE edges (src TEXT, dst TEXT); INSERT INTO edges VALUES ('a', 'b'), ('b', 'c');");
+        let mut e = RustEngine::new();
+        let file = db.0.file_name().unwrap().to_str().unwrap().to_string();
+        let program = format!(r#".input edge from sqlite "{}" table "edges""#, file);
+        assert_eq!(Ok(2), input(&mut e, &db, &program));
+        assert_eq!(1, ask(&e, "edge(a, X)?").len());
+    }
+
+    #[test]
+    fn test_nulls_are_errors() {
+        let db = Db::new("nulls", PEOPLE);
+        let mut e = RustEngine::new();
+        let file = db.0.file_name().unwrap().to_str().unwrap().to_string();
+        let program = format!(r#".input person from sqlite "{}" table "people" columns "full_name, city""#, file);
+        let err = input(&mut e, &db, &program).unwrap_err().to_string();
+        assert!(err.ends_with("row 2: city is NULL"), "{}", err);
+        // row 1 was fine, but doesn't get in without the rest
+        assert_eq!(Ok(None), e.query(parser::query("person(N, C)").unwrap()));
+    }
+
+    #[test]
+    fn test_attached_relations_stay_as_attached() {
+        let db = Db::new("attached", PEOPLE);
+        let mut e = RustEngine::new();
+        let file = db.0.file_name().unwrap().to_str().unwrap().to_string();
+        let program = format!(r#".input person from sqlite "{}" table "people" columns "full_name, age""#, file);
+        let attach = match parser::statement(&program) {
+            Ok((_, Statement::Input(d))) => d,
+            x => panic!("not a directive {:?}", x),
+        };
+        let dir = db.0.parent().unwrap();
+        assert_eq!(Ok(2), crate::directives::input(&mut e, dir, &attach));
+        assert_eq!(
+            Err(Error::Schema("person is already attached".to_string())),
+            crate::directives::input(&mut e, dir, &attach)
+        );
+
+        let rule = match parser::statement("person(zed, X) :- person(_, X).") {
+            Ok((_, Statement::Rule(r))) => r,
+            x => panic!("not a rule {:?}", x),
+        };
+        assert_eq!(
+            Err(Error::Schema("person is read only, rules can't add to it".to_string())),
+            e.push_rule(rule)
+        );
+        assert_eq!(2, ask(&e, "person(N, A)?").len());
+    }
+}
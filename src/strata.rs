@@ -0,0 +1,228 @@
+#![allow(unused_imports, dead_code)]
+
+use std::collections::HashMap;
+
+/*
+ * splits a program into strata: groups of rules that have to be run to a
+ * fixpoint together, in an order where every stratum only reads relations
+ * that earlier strata have finished
+ */
+use crate::engine::{Atom, CompiledRule, Goal, RelKey};
+
+/// Groups rules by the strongly connected components of the dependency graph
+/// between the relations they derive, returned as positions into `rules`.
+/// Rules for mutually recursive relations land in the same stratum, and a
+/// stratum comes after every stratum it reads from, negated, counted or not.
+pub fn strata(rules: &[&CompiledRule]) -> Vec<Vec<usize>> {
+    let graph = Graph::new(rules);
+    // tarjan finishes a component only after everything it can reach, so
+    // dependencies already come first
+    graph
+        .components
+        .iter()
+        .map(|component| {
+            (0..rules.len())
+                .filter(|r| component.contains(&graph.node[&rules[*r].head.relation]))
+                .collect()
+        })
+        .collect()
+}
+
+/// A relation that's negated inside its own stratum, which leaves no order
+/// to evaluate the rules in, as `(head, negated)` of the first rule doing it.
+/// `win(X) :- move(X, Y), !win(Y).` gives `(win, win)`.
+pub fn negative_cycle(rules: &[&CompiledRule]) -> Option<(RelKey, RelKey)> {
+    cycle(rules, |goal| match goal {
+        Goal::Not(a) => Some(a),
+        _ => None,
+    })
+}
+
+/// Like `negative_cycle`, but for a relation counted inside its own stratum,
+/// as `(head, counted)`.
+pub fn counting_cycle(rules: &[&CompiledRule]) -> Option<(RelKey, RelKey)> {
+    cycle(rules, |goal| match goal {
+        Goal::Count { atom, .. } => Some(atom),
+        _ => None,
+    })
+}
+
+// the first rule where an atom `through` picks out is in the head's stratum
+fn cycle(rules: &[&CompiledRule], through: impl Fn(&Goal) -> Option<&Atom>) -> Option<(RelKey, RelKey)> {
+    let graph = Graph::new(rules);
+    let component = |relation: &RelKey| {
+        let n = graph.node.get(relation)?;
+        graph.components.iter().position(|c| c.contains(n))
+    };
+    for rule in rules {
+        for goal in &rule.body {
+            if let Some(a) = through(goal) {
+                match (component(&rule.head.relation), component(&a.relation)) {
+                    (Some(head), Some(read)) if head == read => {
+                        return Some((rule.head.relation, a.relation))
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    None
+}
+
+// the derived relations as nodes, an edge from each rule head to every
+// derived relation its body reads
+struct Graph {
+    node: HashMap<RelKey, usize>,
+    components: Vec<Vec<usize>>,
+}
+
+impl Graph {
+    fn new(rules: &[&CompiledRule]) -> Graph {
+        let mut heads: Vec<RelKey> = vec![];
+        for rule in rules {
+            if !heads.contains(&rule.head.relation) {
+                heads.push(rule.head.relation);
+            }
+        }
+        let node: HashMap<RelKey, usize> = heads.iter().enumerate().map(|(i, h)| (*h, i)).collect();
+        let mut edges = vec![vec![]; heads.len()];
+        for rule in rules {
+            for goal in &rule.body {
+                if let Goal::Atom(a) | Goal::Not(a) | Goal::Count { atom: a, .. } = goal {
+                    if let Some(to) = node.get(&a.relation) {
+                        edges[node[&rule.head.relation]].push(*to);
+                    }
+                }
+            }
+        }
+
+        let mut tarjan = Tarjan {
+            edges: &edges,
+            index: vec![None; heads.len()],
+            low: vec![0; heads.len()],
+            on_stack: vec![false; heads.len()],
+            stack: vec![],
+            next: 0,
+            components: vec![],
+        };
+        for n in 0..heads.len() {
+            if tarjan.index[n].is_none() {
+                tarjan.visit(n);
+            }
+        }
+        let components = tarjan.components;
+        Graph { node, components }
+    }
+}
+
+struct Tarjan<'a> {
+    edges: &'a [Vec<usize>],
+    index: Vec<Option<usize>>,
+    low: Vec<usize>,
+    on_stack: Vec<bool>,
+    stack: Vec<usize>,
+    next: usize,
+    components: Vec<Vec<usize>>,
+}
+
+impl<'a> Tarjan<'a> {
+    fn visit(&mut self, n: usize) {
+        self.index[n] = Some(self.next);
+        self.low[n] = self.next;
+        self.next += 1;
+        self.stack.push(n);
+        self.on_stack[n] = true;
+
+        for to in self.edges[n].iter().cloned() {
+            match self.index[to] {
+                None => {
+                    self.visit(to);
+                    self.low[n] = self.low[n].min(self.low[to]);
+                }
+                Some(i) if self.on_stack[to] => self.low[n] = self.low[n].min(i),
+                Some(_) => {}
+            }
+        }
+
+        if Some(self.low[n]) == self.index[n] {
+            let mut component = vec![];
+            loop {
+                let m = self.stack.pop().unwrap();
+                self.on_stack[m] = false;
+                component.push(m);
+                if m == n {
+                    break;
+                }
+            }
+            self.components.push(component);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Atom, Term};
+    use crate::intern::Interner;
+
+    fn rule(i: &mut Interner, head: &str, body: Vec<&str>) -> CompiledRule {
+        let atom = |i: &mut Interner, name: &str| Atom {
+            relation: (i.intern(name), 1),
+            terms: vec![Term::Var(0)],
+        };
+        CompiledRule {
+            head: atom(i, head),
+            body: body.iter().map(|b| Goal::Atom(atom(i, b))).collect(),
+            var_count: 1,
+        }
+    }
+
+    #[test]
+    fn test_dependencies_come_first() {
+        // c :- b.  a :- edge.  b :- a.  b :- b.
+        let mut i = Interner::new();
+        let rules = [
+            rule(&mut i, "c", vec!["b"]),
+            rule(&mut i, "a", vec!["edge"]),
+            rule(&mut i, "b", vec!["a"]),
+            rule(&mut i, "b", vec!["b"]),
+        ];
+        let refs: Vec<&CompiledRule> = rules.iter().collect();
+        assert_eq!(vec![vec![1], vec![2, 3], vec![0]], strata(&refs));
+    }
+
+    #[test]
+    fn test_mutual_recursion_shares_a_stratum() {
+        // even :- zero.  even :- odd.  odd :- even.
+        let mut i = Interner::new();
+        let rules = [
+            rule(&mut i, "even", vec!["zero"]),
+            rule(&mut i, "even", vec!["odd"]),
+            rule(&mut i, "odd", vec!["even"]),
+        ];
+        let refs: Vec<&CompiledRule> = rules.iter().collect();
+        assert_eq!(vec![vec![0, 1, 2]], strata(&refs));
+    }
+
+    #[test]
+    fn test_negation_across_strata_only() {
+        // reached :- edge.  unreached :- node, !reached.  win :- move, !win.
+        let mut i = Interner::new();
+        let mut rules = vec![
+            rule(&mut i, "reached", vec!["edge"]),
+            rule(&mut i, "unreached", vec!["node"]),
+        ];
+        let reached = rules[0].head.clone();
+        rules[1].body.push(Goal::Not(reached));
+        let refs: Vec<&CompiledRule> = rules.iter().collect();
+        assert_eq!(vec![vec![0], vec![1]], strata(&refs));
+        assert_eq!(None, negative_cycle(&refs));
+
+        let mut win = rule(&mut i, "win", vec!["move"]);
+        win.body.push(Goal::Not(win.head.clone()));
+        rules.push(win);
+        let refs: Vec<&CompiledRule> = rules.iter().collect();
+        let key = rules[2].head.relation;
+        assert_eq!(Some((key, key)), negative_cycle(&refs));
+    }
+}
@@ -0,0 +1,116 @@
+#![allow(unused_imports, dead_code)]
+
+/*
+ * repl transcripts as tests, the same shape the engine tests have in their
+ * comments:
+ *
+ *   % anything before the first > says what the transcript is about
+ *   > edge(a, b).
+ *   > edge(a, c).
+ *   > edge(a, X)?
+ *   edge(a, c).
+ *   edge(a, b).
+ *
+ * each > line gets run on its own and has to print exactly the lines under
+ * it, in any order. % lines are comments wherever they are. a transcript
+ * starting with "% skip: reason" isn't run, for things that don't work yet
+ */
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    /// where the > line is, counting from 1
+    pub line: usize,
+    pub input: String,
+    pub expected: Vec<String>,
+}
+
+pub fn steps(text: &str) -> Vec<Step> {
+    let mut steps: Vec<Step> = vec![];
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if let Some(input) = line.strip_prefix('>') {
+            steps.push(Step {
+                line: n + 1,
+                input: input.trim().to_string(),
+                expected: vec![],
+            });
+        } else if !line.is_empty() && !line.starts_with('%') {
+            if let Some(step) = steps.last_mut() {
+                step.expected.push(line.to_string());
+            }
+        }
+    }
+    steps
+}
+
+/// why the transcript shouldn't be run, if it says so on its first line
+pub fn skipped(text: &str) -> Option<&str> {
+    let first = text.lines().next()?.trim();
+    first.strip_prefix('%')?.trim().strip_prefix("skip:").map(|reason| reason.trim())
+}
+
+/// Runs each step with `run` and describes every one that printed something
+/// other than expected.
+pub fn check<F: FnMut(&str) -> Vec<String>>(steps: &[Step], mut run: F) -> Vec<String> {
+    let mut failures = vec![];
+    for step in steps {
+        let mut got = run(&step.input);
+        let mut expected = step.expected.clone();
+        got.sort();
+        expected.sort();
+        if got != expected {
+            failures.push(format!(
+                "line {}: > {}\n  expected:\n{}\n  got:\n{}",
+                step.line,
+                step.input,
+                indented(&step.expected),
+                indented(&got)
+            ));
+        }
+    }
+    failures
+}
+
+fn indented(lines: &[String]) -> String {
+    if lines.is_empty() {
+        return "    (nothing)".to_string();
+    }
+    lines.iter().map(|l| format!("    {}", l)).collect::<Vec<_>>().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_steps() {
+        let text = "
+            % what this is about
+            > edge(a, b).
+            > % a comment
+            > edge(a, X)?
+            edge(a, b).
+            % not an answer
+            edge(a, c).
+        ";
+        let steps = steps(text);
+        assert_eq!(3, steps.len());
+        assert_eq!(Step { line: 3, input: "edge(a, b).".to_string(), expected: vec![] }, steps[0]);
+        assert_eq!("% a comment", steps[1].input);
+        assert_eq!(vec!["edge(a, b).", "edge(a, c)."], steps[2].expected);
+        assert_eq!(None, skipped(text));
+        assert_eq!(Some("no aggregates yet"), skipped("% skip: no aggregates yet\n> x(a)?"));
+    }
+
+    #[test]
+    fn test_answers_in_any_order() {
+        let steps = steps("> edge(a, X)?\nedge(a, b).\nedge(a, c).\n> edge(b, X)?");
+        let answers = |input: &str| match input {
+            "edge(a, X)?" => vec!["edge(a, c).".to_string(), "edge(a, b).".to_string()],
+            _ => vec!["edge(b, a).".to_string()],
+        };
+        let failures = check(&steps, answers);
+        assert_eq!(1, failures.len());
+        assert!(failures[0].starts_with("line 4: > edge(b, X)?"), "{}", failures[0]);
+    }
+}
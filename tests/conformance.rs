@@ -0,0 +1,24 @@
+// runs every transcript in tests/conformance through `datalog test`, see
+// src/transcript.rs for what they look like
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn conformance() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("conformance");
+    let mut files: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "dl"))
+        .collect();
+    files.sort();
+    assert!(!files.is_empty(), "no transcripts in {}", dir.display());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_datalog"))
+        .arg("test")
+        .args(&files)
+        .output()
+        .unwrap();
+    let report = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}{}", report, String::from_utf8_lossy(&output.stderr));
+}